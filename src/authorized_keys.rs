@@ -0,0 +1,286 @@
+use chrono::{DateTime, Utc};
+use base64;
+use database::{Database, Host, User};
+use public_key::Algorithm;
+use std::error::Error;
+
+pub const MANAGED_BLOCK_BEGIN: &'static str = "# BEGIN ssh-permit-a38";
+pub const MANAGED_BLOCK_END: &'static str = "# END ssh-permit-a38";
+
+// authorized_keys options without a value, see sshd(8) AUTHORIZED_KEYS FILE FORMAT
+const FLAG_OPTIONS: &'static [&'static str] = &[
+    "agent-forwarding",
+    "cert-authority",
+    "no-agent-forwarding",
+    "no-port-forwarding",
+    "no-pty",
+    "no-touch-required",
+    "no-user-rc",
+    "no-X11-forwarding",
+    "port-forwarding",
+    "pty",
+    "restrict",
+    "user-rc",
+    "verify-required",
+    "X11-forwarding",
+];
+
+// authorized_keys options with a quoted value
+const VALUE_OPTIONS: &'static [&'static str] = &[
+    "command",
+    "environment",
+    "expiry-time",
+    "from",
+    "permitlisten",
+    "permitopen",
+    "principals",
+    "tunnel",
+];
+
+// option names are case insensitive
+fn option_lookup(options: &[&'static str], name: &str) -> Option<&'static str> {
+    options
+        .iter()
+        .position(|o| o.to_lowercase() == name.to_lowercase())
+        .map(|i| options[i])
+}
+
+// e.g. from=10.0.0.0/8 => from="10.0.0.0/8"
+pub fn option_normalize(option: &str) -> Result<String, Box<Error>> {
+    let mut name_value = option.trim().splitn(2, '=');
+    let name = name_value.next().unwrap_or("");
+
+    let flag_option = option_lookup(FLAG_OPTIONS, name);
+    let value_option = option_lookup(VALUE_OPTIONS, name);
+
+    match (name_value.next(), flag_option, value_option) {
+        (None, Some(o), _) => Ok(o.to_owned()),
+        (Some(value), _, Some(o)) => {
+            let mut value = value;
+            if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                value = &value[1..value.len() - 1];
+            }
+
+            if value.is_empty() {
+                return Err(From::from(format!("Option {} requires a value", o)));
+            }
+
+            Ok(format!(
+                "{}=\"{}\"",
+                o,
+                value.replace("\\\"", "\"").replace("\"", "\\\"")
+            ))
+        }
+        (None, None, Some(o)) => Err(From::from(format!("Option {} requires a value", o))),
+        (Some(_), Some(o), None) => Err(From::from(format!(
+            "Option {} does not take a value",
+            o
+        ))),
+        _ => Err(From::from(format!(
+            "Unknown authorized_keys option {}",
+            name
+        ))),
+    }
+}
+
+pub fn options_normalize(options: &[&str]) -> Result<Vec<String>, Box<Error>> {
+    let mut options_normalized: Vec<String> = Vec::new();
+
+    for option in options {
+        let option = option_normalize(option)?;
+        if !options_normalized.contains(&option) {
+            options_normalized.push(option);
+        }
+    }
+
+    Ok(options_normalized)
+}
+
+// e.g. from="10.0.0.0/8",no-pty ssh-ed25519 AAAA...
+pub fn key_line(options: &[String], public_key: &str) -> String {
+    if options.is_empty() {
+        return public_key.to_owned();
+    }
+
+    format!("{} {}", options.join(","), public_key)
+}
+
+fn lines_join(lines: &[&str]) -> String {
+    lines.iter().map(|l| format!("{}\n", l)).collect()
+}
+
+// splits authorized_keys content into the lines before, inside and after the managed block
+pub fn managed_block_split(content: &str) -> Result<(String, String, String), Box<Error>> {
+    let lines: Vec<&str> = content.lines().collect();
+    let begin = lines.iter().position(|l| l.trim() == MANAGED_BLOCK_BEGIN);
+    let end = lines.iter().position(|l| l.trim() == MANAGED_BLOCK_END);
+
+    match (begin, end) {
+        // no managed block yet, it's appended
+        (None, None) => Ok((lines_join(&lines), String::new(), String::new())),
+        (Some(b), Some(e)) if b < e => Ok((
+            lines_join(&lines[..b]),
+            lines_join(&lines[b + 1..e]),
+            lines_join(&lines[e + 1..]),
+        )),
+        _ => Err(From::from(format!(
+            "Malformed managed block, expected a \"{}\" line followed by a \"{}\" line",
+            MANAGED_BLOCK_BEGIN, MANAGED_BLOCK_END
+        ))),
+    }
+}
+
+pub fn managed_block_join(before: &str, managed: &str, after: &str) -> String {
+    format!(
+        "{}{}\n{}{}\n{}",
+        before, MANAGED_BLOCK_BEGIN, managed, MANAGED_BLOCK_END, after
+    )
+}
+
+fn user_block(user: &User, options: &[String]) -> String {
+    // build e.g.
+    // # mail@example.com
+    // ssh-rsa ...
+    // from="10.0.0.0/8" ssh-ed25519 ...
+    let mut block = format!("# {}", user.user_id);
+    for public_key in &user.public_keys {
+        block.push_str(&format!("\n{}", key_line(options, &public_key.key)));
+    }
+    block
+}
+
+// authorized_keys content of an account of a host according to the database
+pub fn render(db: &Database, host: &Host, account: Option<&str>, now: &DateTime<Utc>) -> String {
+    // collect authorized_keys to sync ...
+    let mut authorized_keys_sync_vec: Vec<String> = Vec::new();
+    let mut authorized_user_ids: Vec<&str> = Vec::new();
+
+    // ... 1. on user level, direct grants of the host take precedence over host group ones
+    for (authorized_user, _) in db.host_user_grants(&host) {
+        if authorized_user.is_expired(now) || !authorized_user.is_account(account) {
+            continue;
+        }
+
+        for user in &db.users {
+            if user.user_id == authorized_user.id
+                && !authorized_user_ids.contains(&&*user.user_id)
+            {
+                authorized_keys_sync_vec
+                    .append(&mut vec![user_block(&user, &authorized_user.options)]);
+                authorized_user_ids.push(&user.user_id);
+            }
+        }
+    }
+
+    // ... 2. on group level, options of a user level grant take precedence
+    for (authorized_group, _) in db.host_user_group_grants(&host) {
+        if authorized_group.is_expired(now) || !authorized_group.is_account(account) {
+            continue;
+        }
+
+        for group in &db.user_groups {
+            if authorized_group.id == group.group_id {
+                // members of nested groups too
+                for (user_id, _) in db.group_members(&group) {
+                    if authorized_user_ids.contains(&user_id) {
+                        continue;
+                    }
+
+                    for user in &db.users {
+                        if user_id == user.user_id {
+                            authorized_keys_sync_vec
+                                .append(&mut vec![user_block(&user, &authorized_group.options)]);
+                            authorized_user_ids.push(&user.user_id);
+                            break;
+                        }
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    authorized_keys_sync_vec.sort();
+    authorized_keys_sync_vec.dedup();
+
+    format!("{}\n", authorized_keys_sync_vec.join("\n\n"))
+}
+
+// returns the part of the remote content managed by ssh-permit-a38 and the content to upload
+pub fn merge(host: &Host, remote: &str, rendered: &str) -> Result<(String, String), Box<Error>> {
+    // managed block mode: lines outside of the block are kept untouched
+    if host.managed_block {
+        let (before, managed, after) = managed_block_split(remote)?;
+        return Ok((managed, managed_block_join(&before, rendered, &after)));
+    }
+
+    Ok((remote.to_owned(), rendered.to_owned()))
+}
+
+// is the public key (SSH wire format blob) in one of the key lines, options are skipped
+pub fn contains_key(content: &str, blob: &[u8]) -> bool {
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        for i in 1..fields.len() {
+            if Algorithm::from_name(fields[i - 1]).is_some()
+                && base64::decode(fields[i]).ok().as_ref().map(|b| &b[..]) == Some(blob)
+            {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC_KEY_FOO1: &'static str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAINZ4NANG2PI6O7Ldl7NusZCRgYZ+jMwH8cEE47wpqBKc foo1@example.com";
+    const PUBLIC_KEY_FOO3: &'static str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAILP+b1okxbE9KhagzVFpFi8WGRXYjtc7nwlS0lHg/oCX foo3@example.com";
+
+    fn blob(public_key: &str) -> Vec<u8> {
+        base64::decode(public_key.split_whitespace().nth(1).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn contains_key_plain() {
+        let content = format!("{}\n{}\n", PUBLIC_KEY_FOO3, PUBLIC_KEY_FOO1);
+        assert!(contains_key(&content, &blob(PUBLIC_KEY_FOO1)));
+    }
+
+    #[test]
+    fn contains_key_options() {
+        let content = format!("from=\"10.0.0.0/8\",no-pty {}", PUBLIC_KEY_FOO1);
+        assert!(contains_key(&content, &blob(PUBLIC_KEY_FOO1)));
+
+        // quoted option values with spaces
+        let content = format!("command=\"echo hello world\",restrict {}", PUBLIC_KEY_FOO1);
+        assert!(contains_key(&content, &blob(PUBLIC_KEY_FOO1)));
+    }
+
+    #[test]
+    fn contains_key_commented_out() {
+        let content = format!("# {}\n#{}", PUBLIC_KEY_FOO1, PUBLIC_KEY_FOO1);
+        assert!(!contains_key(&content, &blob(PUBLIC_KEY_FOO1)));
+    }
+
+    #[test]
+    fn contains_key_other_key_same_algorithm() {
+        assert!(!contains_key(PUBLIC_KEY_FOO3, &blob(PUBLIC_KEY_FOO1)));
+    }
+
+    #[test]
+    fn contains_key_empty() {
+        assert!(!contains_key("", &blob(PUBLIC_KEY_FOO1)));
+        assert!(!contains_key("\n\n", &blob(PUBLIC_KEY_FOO1)));
+    }
+}
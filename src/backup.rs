@@ -0,0 +1,205 @@
+use chrono::Utc;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone)]
+pub struct BackupOptions {
+    // local backups, <dir>/<hostname>/<backup id>
+    pub dir: PathBuf,
+    // number of backups kept per host, 0 keeps all
+    pub retention: usize,
+}
+
+// e.g. 20180818T120000.123456Z, sorts chronologically. Microseconds keep syncs within the same
+// second from overwriting each other's backup
+pub fn id_now() -> String {
+    Utc::now().format("%Y%m%dT%H%M%S%.6fZ").to_string()
+}
+
+// e.g. /home/foo/.ssh/authorized_keys.ssh-permit-20180818T120000.123456Z.bak
+pub fn remote_path(authorized_keys_file: &str, id: &str) -> String {
+    format!("{}.ssh-permit-{}.bak", authorized_keys_file, id)
+}
+
+// backup id of a remote backup file name, None for other files.
+// Files that merely look alike, e.g. authorized_keys.ssh-permit-old.bak, are never pruned
+pub fn remote_id(authorized_keys_file_name: &str, file_name: &str) -> Option<String> {
+    let prefix = format!("{}.ssh-permit-", authorized_keys_file_name);
+    if !file_name.starts_with(&prefix) || !file_name.ends_with(".bak")
+        || file_name.len() < prefix.len() + ".bak".len()
+    {
+        return None;
+    }
+
+    let id = &file_name[prefix.len()..file_name.len() - ".bak".len()];
+    let id_valid = id.starts_with(|c: char| c.is_digit(10)) && id.ends_with('Z')
+        && id.chars().all(|c| c.is_digit(10) || c == 'T' || c == '.' || c == 'Z');
+
+    if id_valid {
+        return Some(id.to_owned());
+    }
+    None
+}
+
+// ids of backups beyond retention, oldest first
+pub fn expired_ids(ids: &[String], retention: usize) -> Vec<String> {
+    let mut ids = ids.to_vec();
+    ids.sort();
+
+    if retention == 0 || ids.len() <= retention {
+        return vec![];
+    }
+
+    let expired = ids.len() - retention;
+    ids.truncate(expired);
+    ids
+}
+
+fn local_host_dir(options: &BackupOptions, hostname: &str) -> PathBuf {
+    // hostname:port is no valid directory name on every platform
+    options.dir.join(hostname.replace(":", "_"))
+}
+
+pub fn local_save(
+    options: &BackupOptions,
+    hostname: &str,
+    id: &str,
+    content: &str,
+) -> Result<PathBuf, Box<Error>> {
+    let host_dir = local_host_dir(&options, &hostname);
+    fs::create_dir_all(&host_dir)?;
+
+    let path = host_dir.join(id);
+    let mut file = File::create(&path)?;
+    file.write_all(content.as_bytes())?;
+
+    // prune old backups
+    for id in expired_ids(&local_list(&options, &hostname)?, options.retention) {
+        fs::remove_file(host_dir.join(id))?;
+    }
+
+    Ok(path)
+}
+
+// ids of local backups of a host, oldest first
+pub fn local_list(options: &BackupOptions, hostname: &str) -> Result<Vec<String>, Box<Error>> {
+    let host_dir = local_host_dir(&options, &hostname);
+    let mut ids = Vec::new();
+
+    if !Path::new(&host_dir).exists() {
+        return Ok(ids);
+    }
+
+    for entry in fs::read_dir(&host_dir)? {
+        if let Some(id) = entry?.file_name().to_str() {
+            ids.push(id.to_owned());
+        }
+    }
+
+    ids.sort();
+    Ok(ids)
+}
+
+pub fn local_read(options: &BackupOptions, hostname: &str, id: &str) -> Result<String, Box<Error>> {
+    let mut content = String::new();
+    File::open(local_host_dir(&options, &hostname).join(id))?.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn expired_ids_retention() {
+        let backups = ids(&[
+            "20180818T120002.000000Z",
+            "20180818T120000.000000Z",
+            "20180818T120001.000000Z",
+        ]);
+
+        // at the boundary nothing is removed
+        assert!(expired_ids(&backups, 3).is_empty());
+        assert!(expired_ids(&backups, 4).is_empty());
+
+        // oldest first
+        assert_eq!(
+            expired_ids(&backups, 2),
+            ids(&["20180818T120000.000000Z"])
+        );
+        assert_eq!(
+            expired_ids(&backups, 1),
+            ids(&["20180818T120000.000000Z", "20180818T120001.000000Z"])
+        );
+
+        // 0 keeps all
+        assert!(expired_ids(&backups, 0).is_empty());
+        assert!(expired_ids(&[], 1).is_empty());
+    }
+
+    #[test]
+    fn expired_ids_same_second() {
+        let backups = ids(&[
+            "20180818T120000.500000Z",
+            "20180818T120000.100000Z",
+            "20180818T120001Z",
+            "20180818T120000Z",
+        ]);
+
+        assert_eq!(
+            expired_ids(&backups, 2),
+            ids(&["20180818T120000.100000Z", "20180818T120000.500000Z"])
+        );
+    }
+
+    #[test]
+    fn id_now_sorts_chronologically() {
+        let first = id_now();
+        let second = id_now();
+
+        assert!(first <= second);
+        assert_eq!(
+            remote_id("authorized_keys", &remote_path("authorized_keys", &first)),
+            Some(first)
+        );
+    }
+
+    #[test]
+    fn remote_ids() {
+        assert_eq!(
+            remote_id(
+                "authorized_keys",
+                "authorized_keys.ssh-permit-20180818T120000.123456Z.bak"
+            ),
+            Some("20180818T120000.123456Z".to_owned())
+        );
+
+        // unparsable names are never pruned
+        assert_eq!(remote_id("authorized_keys", "authorized_keys"), None);
+        assert_eq!(
+            remote_id("authorized_keys", "authorized_keys.ssh-permit-tmp-20180818T120000Z"),
+            None
+        );
+        assert_eq!(
+            remote_id("authorized_keys", "authorized_keys2.ssh-permit-20180818T120000Z.bak"),
+            None
+        );
+        assert_eq!(
+            remote_id("authorized_keys", "authorized_keys.ssh-permit-20180818T120000Z.bak.orig"),
+            None
+        );
+        assert_eq!(
+            remote_id("authorized_keys", "authorized_keys.ssh-permit-old.bak"),
+            None
+        );
+        assert_eq!(remote_id("authorized_keys", "authorized_keys.ssh-permit-.bak"), None);
+        assert_eq!(remote_id("authorized_keys", "authorized_keys.ssh-permit.bak"), None);
+        assert_eq!(remote_id("authorized_keys", "id_ed25519.pub"), None);
+    }
+}
@@ -1,8 +1,39 @@
 use colored::Colorize;
+use std::cell::RefCell;
 use std::io;
 use std::io::Write;
 use std::process::exit;
 
+thread_local! {
+    // output of the current thread is collected here instead of printed, see capture_start
+    static CAPTURE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+// collect the output of okln, warningln and infoln of the current thread until capture_end,
+// e.g. to print the output of a host synced in a worker thread in one piece
+pub fn capture_start() {
+    CAPTURE.with(|c| *c.borrow_mut() = Some(String::new()));
+}
+
+pub fn capture_end() -> String {
+    CAPTURE.with(|c| c.borrow_mut().take().unwrap_or(String::new()))
+}
+
+fn outputln(line: String) {
+    let captured = CAPTURE.with(|c| match *c.borrow_mut() {
+        Some(ref mut output) => {
+            output.push_str(&line);
+            output.push('\n');
+            true
+        }
+        None => false,
+    });
+
+    if !captured {
+        println!("{}", line);
+    }
+}
+
 pub fn errorln(msg: &str) {
     println!("{} {}", "Error:".red(), msg);
     exit(1);
@@ -61,13 +92,13 @@ pub fn read_line<'a>(msg: &str, default: &'a String) -> String {
 }
 
 pub fn okln(msg: &str) {
-    println!("{}", msg.green().bold());
+    outputln(format!("{}", msg.green().bold()));
 }
 
 pub fn warningln(msg: &str) {
-    println!("{} {}", "Warning:".magenta(), msg);
+    outputln(format!("{} {}", "Warning:".magenta(), msg));
 }
 
 pub fn infoln(msg: &str) {
-    println!("{}", msg);
+    outputln(msg.to_owned());
 }
@@ -1,13 +1,16 @@
 extern crate serde;
 extern crate serde_json;
 
-use chrono::Utc;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use public_key;
+use public_key::{Algorithm, PublicKey};
+use serde::{Deserialize, Deserializer};
 use std::error::Error;
 use std::fmt;
 use std::fs::File;
 use std::path::Path;
 
-const SCHEMA_VERSION: &'static str = "0.1.0";
+const SCHEMA_VERSION: &'static str = "0.2.0";
 
 #[derive(Serialize, Deserialize)]
 pub struct Database {
@@ -15,16 +18,35 @@ pub struct Database {
     pub users: Vec<User>,
     pub user_groups: Vec<UserGroup>,
 
+    #[serde(default)]
+    pub host_groups: Vec<HostGroup>,
+
+    #[serde(default)]
+    pub key_policy: KeyPolicy,
+
+    // number of authorized_keys backups kept per host, 0 keeps all
+    #[serde(default = "backup_retention_default")]
+    pub backup_retention: usize,
+
     pub modified_at: String,
     pub schema_version: String,
 }
 
+fn backup_retention_default() -> usize {
+    10
+}
+
 impl Default for Database {
     fn default() -> Database {
         Database {
             hosts: vec![],
             users: vec![],
             user_groups: vec![],
+            host_groups: vec![],
+            key_policy: KeyPolicy {
+                ..Default::default()
+            },
+            backup_retention: backup_retention_default(),
             modified_at: String::from(""),
             schema_version: SCHEMA_VERSION.to_owned(),
         }
@@ -34,7 +56,25 @@ impl Default for Database {
 impl Database {
     pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<Database, Box<Error>> {
         let file = File::open(path)?;
-        Ok(serde_json::from_reader(file)?)
+        let mut db: Database = serde_json::from_reader(file)?;
+        db.migrate();
+        Ok(db)
+    }
+
+    fn migrate(&mut self) {
+        // < 0.2.0: single public_key per user => public_keys
+        for user in &mut self.users {
+            if let Some(public_key) = user.public_key.take() {
+                user.public_keys.insert(
+                    0,
+                    UserPublicKey {
+                        key: public_key,
+                        label: String::from("default"),
+                        added_at: self.modified_at.to_owned(),
+                    },
+                );
+            }
+        }
     }
 
     pub fn save<P: AsRef<Path>>(&mut self, path: P) {
@@ -72,6 +112,148 @@ impl Database {
             .map(|i| &self.hosts[i])
     }
 
+    pub fn host_group_get(&self, group_id: &str) -> Option<&HostGroup> {
+        self.host_groups
+            .iter()
+            .position(|g| g.group_id == group_id)
+            .map(|i| &self.host_groups[i])
+    }
+
+    pub fn host_group_get_mut(&mut self, group_id: &str) -> Option<&mut HostGroup> {
+        self.host_groups
+            .iter()
+            .position(|g| g.group_id == group_id)
+            .map(move |i| &mut self.host_groups[i])
+    }
+
+    pub fn host_groups_of(&self, host: &Host) -> Vec<&HostGroup> {
+        self.host_groups
+            .iter()
+            .filter(|g| g.hosts.contains(&host.hostname))
+            .collect()
+    }
+
+    // user grants of a host, direct ones first, then the ones of its host groups
+    pub fn host_user_grants<'a>(&'a self, host: &'a Host) -> Vec<(&'a Grant, Option<&'a HostGroup>)> {
+        let mut grants: Vec<(&Grant, Option<&HostGroup>)> =
+            host.authorized_users.iter().map(|g| (g, None)).collect();
+
+        for host_group in self.host_groups_of(&host) {
+            for grant in &host_group.authorized_users {
+                grants.push((grant, Some(host_group)));
+            }
+        }
+
+        grants
+    }
+
+    // group grants of a host, direct ones first, then the ones of its host groups
+    pub fn host_user_group_grants<'a>(
+        &'a self,
+        host: &'a Host,
+    ) -> Vec<(&'a Grant, Option<&'a HostGroup>)> {
+        let mut grants: Vec<(&Grant, Option<&HostGroup>)> =
+            host.authorized_user_groups.iter().map(|g| (g, None)).collect();
+
+        for host_group in self.host_groups_of(&host) {
+            for grant in &host_group.authorized_user_groups {
+                grants.push((grant, Some(host_group)));
+            }
+        }
+
+        grants
+    }
+
+    // accounts to sync, None is the account chosen on sync (--ssh-user, ssh_config or prompt).
+    // It's left out if all grants are for declared accounts
+    pub fn host_sync_accounts(&self, host: &Host) -> Vec<Option<String>> {
+        let mut accounts: Vec<Option<String>> = Vec::new();
+
+        if host.accounts.is_empty()
+            || self.host_user_grants(&host)
+                .iter()
+                .chain(self.host_user_group_grants(&host).iter())
+                .any(|&(g, _)| g.account.is_none())
+        {
+            accounts.push(None);
+        }
+
+        for account in &host.accounts {
+            accounts.push(Some(account.to_owned()));
+        }
+
+        accounts
+    }
+
+    // who can log into which account of which host and through which grant, as rendered on sync
+    pub fn access(&self, now: &DateTime<Utc>) -> Vec<Access> {
+        let mut access = Vec::new();
+
+        for host in &self.hosts {
+            for (grant, host_group) in self.host_user_grants(&host) {
+                if grant.is_expired(now) {
+                    continue;
+                }
+
+                let path = match host_group {
+                    Some(g) => format!("via host group {}", g),
+                    None => String::from("direct"),
+                };
+
+                if let Some(user) = self.user_get(&grant.id) {
+                    access.push(Access::new(&user, &host, &grant, path));
+                }
+            }
+
+            for (grant, host_group) in self.host_user_group_grants(&host) {
+                if grant.is_expired(now) {
+                    continue;
+                }
+
+                let user_group = match self.group_get(&grant.id) {
+                    Some(g) => g,
+                    None => continue,
+                };
+
+                for (user_id, nested_group) in self.group_members(&user_group) {
+                    let mut path = format!("via group {}", user_group);
+                    if let Some(g) = nested_group {
+                        path = format!("{}, nested group {}", path, g);
+                    }
+                    if let Some(g) = host_group {
+                        path = format!("{}, host group {}", path, g);
+                    }
+
+                    if let Some(user) = self.user_get(user_id) {
+                        access.push(Access::new(&user, &host, &grant, path));
+                    }
+                }
+            }
+        }
+
+        // users without public keys can't log in
+        access.retain(|a| {
+            self.user_get(&a.user_id)
+                .map(|u| !u.public_keys.is_empty())
+                .unwrap_or(false)
+        });
+
+        access
+    }
+
+    pub fn host_group_hosts_sync_todo(&mut self, group_id: &str) {
+        let hostnames = match self.host_group_get(group_id) {
+            Some(g) => g.hosts.to_owned(),
+            None => return,
+        };
+
+        for host in &mut self.hosts {
+            if hostnames.contains(&host.hostname) {
+                host.sync_todo = true;
+            }
+        }
+    }
+
     pub fn user_get(&self, user_id: &str) -> Option<&User> {
         self.users
             .iter()
@@ -79,6 +261,13 @@ impl Database {
             .map(|i| &self.users[i])
     }
 
+    pub fn user_get_mut(&mut self, user_id: &str) -> Option<&mut User> {
+        self.users
+            .iter()
+            .position(|u| u.user_id == user_id)
+            .map(move |i| &mut self.users[i])
+    }
+
     pub fn group_get(&self, group_id: &str) -> Option<&UserGroup> {
         self.user_groups
             .iter()
@@ -93,46 +282,281 @@ impl Database {
             .map(move |i| &mut self.user_groups[i])
     }
 
-    pub fn is_user_granted(&self, user: &User, host: &Host) -> bool {
+    pub fn is_user_granted(&self, user: &User, host: &Host, account: Option<&str>) -> bool {
         host.authorized_users
             .iter()
-            .position(|au| au == &user.user_id)
+            .position(|au| au.id == user.user_id && au.is_account(account))
             .is_some()
     }
 
-    pub fn is_group_granted(&self, user_group: &UserGroup, host: &Host) -> bool {
+    pub fn is_group_granted(
+        &self,
+        user_group: &UserGroup,
+        host: &Host,
+        account: Option<&str>,
+    ) -> bool {
         host.authorized_user_groups
             .iter()
-            .position(|ag| ag == &user_group.group_id)
+            .position(|ag| ag.id == user_group.group_id && ag.is_account(account))
             .is_some()
     }
 
+    pub fn is_user_granted_host_group(
+        &self,
+        user: &User,
+        host_group: &HostGroup,
+        account: Option<&str>,
+    ) -> bool {
+        host_group
+            .authorized_users
+            .iter()
+            .any(|au| au.id == user.user_id && au.is_account(account))
+    }
+
+    pub fn is_group_granted_host_group(
+        &self,
+        user_group: &UserGroup,
+        host_group: &HostGroup,
+        account: Option<&str>,
+    ) -> bool {
+        host_group
+            .authorized_user_groups
+            .iter()
+            .any(|ag| ag.id == user_group.group_id && ag.is_account(account))
+    }
+
+    // directly or through nested groups
     pub fn is_user_group_member(&self, user: &User, user_group: &UserGroup) -> bool {
-        user_group
-            .members
+        self.group_members(&user_group)
             .iter()
-            .position(|u| u == &user.user_id)
-            .is_some()
+            .any(|&(u, _)| u == user.user_id)
+    }
+
+    // the group and its nested groups, transitively. Groups are visited once, cycles end there
+    pub fn group_descendants<'a>(&'a self, user_group: &'a UserGroup) -> Vec<&'a UserGroup> {
+        let mut groups = vec![user_group];
+        let mut i = 0;
+
+        while i < groups.len() {
+            let member_groups = groups[i].member_groups.to_owned();
+            for group_id in member_groups {
+                if groups.iter().any(|g| g.group_id == group_id) {
+                    continue;
+                }
+                if let Some(group) = self.group_get(&group_id) {
+                    groups.push(group);
+                }
+            }
+            i += 1;
+        }
+
+        groups
+    }
+
+    // groups which contain the group, transitively, and the group itself
+    pub fn group_ancestors(&self, group_id: &str) -> Vec<String> {
+        self.user_groups
+            .iter()
+            .filter(|g| {
+                self.group_descendants(&g)
+                    .iter()
+                    .any(|d| d.group_id == group_id)
+            })
+            .map(|g| g.group_id.to_owned())
+            .collect()
+    }
+
+    // user ids of the members, direct ones first, then the ones inherited with their nested group
+    pub fn group_members<'a>(
+        &'a self,
+        user_group: &'a UserGroup,
+    ) -> Vec<(&'a str, Option<&'a UserGroup>)> {
+        let mut members: Vec<(&str, Option<&UserGroup>)> = Vec::new();
+
+        for group in self.group_descendants(&user_group) {
+            let via = if group.group_id == user_group.group_id {
+                None
+            } else {
+                Some(group)
+            };
+
+            for user_id in &group.members {
+                if !members.iter().any(|&(u, _)| u == user_id) {
+                    members.push((user_id, via));
+                }
+            }
+        }
+
+        members
+    }
+
+    // any grant of the host, direct or through a host group, matches
+    fn host_grants_match<U, G>(
+        &self,
+        host: &Host,
+        user_grant_matches: &U,
+        group_grant_matches: &G,
+    ) -> bool
+    where
+        U: Fn(&Grant) -> bool,
+        G: Fn(&Grant) -> bool,
+    {
+        self.host_user_grants(&host).iter().any(|&(g, _)| user_grant_matches(g))
+            || self.host_user_group_grants(&host)
+                .iter()
+                .any(|&(g, _)| group_grant_matches(g))
+    }
+
+    // hosts with any matching grant, direct or through a host group, need a sync
+    fn hosts_sync_todo<U, G>(&mut self, user_grant_matches: U, group_grant_matches: G)
+    where
+        U: Fn(&Grant) -> bool,
+        G: Fn(&Grant) -> bool,
+    {
+        let hostnames: Vec<String> = self
+            .hosts
+            .iter()
+            .filter(|h| self.host_grants_match(&h, &user_grant_matches, &group_grant_matches))
+            .map(|h| h.hostname.to_owned())
+            .collect();
+
+        for host in &mut self.hosts {
+            if hostnames.contains(&host.hostname) {
+                host.sync_todo = true;
+            }
+        }
+    }
+
+    pub fn expired_grants_sync_todo(&mut self, now: &DateTime<Utc>) {
+        self.hosts_sync_todo(|g| g.is_expired(now), |g| g.is_expired(now));
+    }
+
+    // host needs a sync, marked or with lapsed grants not marked yet
+    pub fn host_sync_pending(&self, host: &Host, now: &DateTime<Utc>) -> bool {
+        let expired = |g: &Grant| g.is_expired(now);
+        host.sync_todo || self.host_grants_match(&host, &expired, &expired)
+    }
+
+    pub fn user_hosts_sync_todo(&mut self, user_id: &str) {
+        let user_groups: Vec<String> = self
+            .user_groups
+            .iter()
+            .filter(|g| self.group_members(&g).iter().any(|&(m, _)| m == user_id))
+            .map(|g| g.group_id.to_owned())
+            .collect();
+
+        self.hosts_sync_todo(|g| g.id == user_id, |g| user_groups.contains(&g.id));
+    }
+
+    // members of the group are members of the groups containing it, their hosts need a sync too
+    pub fn user_group_hosts_sync_todo(&mut self, group_id: &str) {
+        let user_groups = self.group_ancestors(group_id);
+        self.hosts_sync_todo(|_| false, |g| user_groups.contains(&g.id));
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+pub struct KeyPolicy {
+    pub min_rsa_bits: usize,
+    pub allowed_algorithms: Vec<String>,
+}
+
+impl Default for KeyPolicy {
+    fn default() -> KeyPolicy {
+        KeyPolicy {
+            min_rsa_bits: 2048,
+            allowed_algorithms: vec![
+                Algorithm::Rsa,
+                Algorithm::Ed25519,
+                Algorithm::EcdsaNistp256,
+                Algorithm::EcdsaNistp384,
+                Algorithm::EcdsaNistp521,
+                Algorithm::SkEcdsaNistp256,
+                Algorithm::SkEd25519,
+            ].iter()
+                .map(|a| a.name().to_owned())
+                .collect(),
+        }
+    }
+}
+
+impl KeyPolicy {
+    pub fn check(&self, public_key: &PublicKey) -> Result<(), String> {
+        if !self
+            .allowed_algorithms
+            .iter()
+            .any(|a| a == public_key.algorithm.name())
+        {
+            return Err(format!(
+                "{} keys are not allowed",
+                public_key.algorithm
+            ));
+        }
+
+        if public_key.algorithm == Algorithm::Rsa && public_key.bits < self.min_rsa_bits {
+            return Err(format!(
+                "RSA key has {} bits, at least {} bits are required",
+                public_key.bits, self.min_rsa_bits
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Host {
     pub hostname: String,
 
     #[serde(default)]
     pub alias: Option<String>,
 
-    pub authorized_users: Vec<String>,
-    pub authorized_user_groups: Vec<String>,
+    // only manage the lines between the BEGIN/END ssh-permit-a38 markers of authorized_keys
+    #[serde(default)]
+    pub managed_block: bool,
+
+    // pinned host keys, verified instead of known_hosts
+    #[serde(default)]
+    pub host_keys: Vec<HostKey>,
+
+    // remote accounts with an authorized_keys of their own, e.g. deploy
+    #[serde(default)]
+    pub accounts: Vec<String>,
+
+    // jump hosts like ProxyJump of ssh_config, e.g. bastion or jump1,deploy@jump2:2222
+    #[serde(default)]
+    pub proxy_jump: Option<String>,
+
+    #[serde(deserialize_with = "deserialize_grants")]
+    pub authorized_users: Vec<Grant>,
+    #[serde(deserialize_with = "deserialize_grants")]
+    pub authorized_user_groups: Vec<Grant>,
     pub sync_todo: bool,
 }
 
+impl Host {
+    pub fn has_account(&self, account: &str) -> bool {
+        self.accounts.iter().any(|a| a == account)
+    }
+}
+
+// e.g. deploy@web1.example.com, hostname only for the account chosen on sync
+pub fn account_label(hostname: &str, account: Option<&str>) -> String {
+    match account {
+        Some(a) => format!("{}@{}", a, hostname),
+        None => hostname.to_owned(),
+    }
+}
+
 impl Default for Host {
     fn default() -> Host {
         Host {
             hostname: String::from(""),
             alias: None,
+            managed_block: false,
+            host_keys: vec![],
+            accounts: vec![],
+            proxy_jump: None,
             authorized_users: vec![],
             authorized_user_groups: vec![],
             sync_todo: true,
@@ -146,10 +570,227 @@ impl fmt::Display for Host {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HostKey {
+    pub algorithm: String,
+    // e.g. SHA256:a5lcfVLkzdMXMaOPcon5tzlRKbyidKwb6TIZpBUcidM
+    pub fingerprint: String,
+    pub added_at: String,
+}
+
+impl fmt::Display for HostKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.algorithm, self.fingerprint)
+    }
+}
+
+// grant of a user or group (id) to a host
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Grant {
+    pub id: String,
+
+    // authorized_keys options, e.g. from="10.0.0.0/8"
+    #[serde(default)]
+    pub options: Vec<String>,
+
+    // RFC 3339, None for grants without time limit
+    #[serde(default)]
+    pub expires_at: Option<String>,
+
+    // remote account of the host, None for the account chosen on sync
+    #[serde(default)]
+    pub account: Option<String>,
+}
+
+impl Grant {
+    // adds the grant, it replaces a grant of the same id and account
+    pub fn set(grants: &mut Vec<Grant>, grant: Grant) {
+        if let Some(g) = grants
+            .iter_mut()
+            .find(|g| g.id == grant.id && g.account == grant.account)
+        {
+            *g = grant;
+            return;
+        }
+
+        grants.push(grant);
+    }
+
+    // e.g. 2018-08-18 => expires 2018-08-18 00:00 UTC
+    pub fn expires_at_until(date: &str) -> Result<String, Box<Error>> {
+        let expires_at = match DateTime::parse_from_rfc3339(date) {
+            Ok(d) => d.with_timezone(&Utc),
+            Err(_) => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                Ok(d) => Utc.from_utc_date(&d).and_hms(0, 0, 0),
+                Err(_) => {
+                    return Err(From::from(format!(
+                        "Invalid date {}, expected e.g. 2018-08-18 or 2018-08-18T12:00:00Z",
+                        date
+                    )))
+                }
+            },
+        };
+
+        // the grant would be expired right away
+        if expires_at <= Utc::now() {
+            return Err(From::from(format!("Date {} is in the past", date)));
+        }
+
+        Ok(expires_at.to_rfc3339())
+    }
+
+    // e.g. 7d => expires in 7 days
+    pub fn expires_at_for(duration: &str) -> Result<String, Box<Error>> {
+        let duration = duration.trim();
+        let invalid = || -> Box<Error> {
+            From::from(format!(
+                "Invalid duration {}, expected e.g. 30m, 12h, 7d or 2w",
+                duration
+            ))
+        };
+
+        if duration.len() < 2 {
+            return Err(invalid());
+        }
+
+        let (amount, unit) = duration.split_at(duration.len() - 1);
+        let amount = match amount.parse::<i64>() {
+            Ok(a) if a > 0 => a,
+            _ => return Err(invalid()),
+        };
+
+        let duration = match unit {
+            "m" => Duration::minutes(amount),
+            "h" => Duration::hours(amount),
+            "d" => Duration::days(amount),
+            "w" => Duration::weeks(amount),
+            _ => return Err(invalid()),
+        };
+
+        let expires_at = Utc::now() + duration;
+        Ok(expires_at.with_nanosecond(0).unwrap().to_rfc3339())
+    }
+
+    pub fn is_account(&self, account: Option<&str>) -> bool {
+        self.account.as_ref().map(|a| &**a) == account
+    }
+
+    pub fn is_expired(&self, now: &DateTime<Utc>) -> bool {
+        match self.expires_at {
+            Some(ref expires_at) => match DateTime::parse_from_rfc3339(expires_at) {
+                Ok(d) => d.with_timezone(&Utc) <= *now,
+                Err(_) => false,
+            },
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for Grant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.id)?;
+        if let Some(ref account) = self.account {
+            write!(f, " as {}", account)?;
+        }
+        if !self.options.is_empty() {
+            write!(f, " [{}]", self.options.join(","))?;
+        }
+        if let Some(ref expires_at) = self.expires_at {
+            if self.is_expired(&Utc::now()) {
+                write!(f, " (expired {})", expires_at)?;
+            } else {
+                write!(f, " (until {})", expires_at)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// a user able to log into an account of a host, path is the grant it comes from
+pub struct Access {
+    pub user_id: String,
+    pub hostname: String,
+    pub account: Option<String>,
+    // e.g. direct, via group gauls, nested group armorica, host group web
+    pub path: String,
+}
+
+impl Access {
+    fn new(user: &User, host: &Host, grant: &Grant, path: String) -> Access {
+        Access {
+            user_id: user.user_id.to_owned(),
+            hostname: host.hostname.to_owned(),
+            account: grant.account.to_owned(),
+            path: path,
+        }
+    }
+
+    // e.g. deploy@web1.example.com
+    pub fn host_label(&self) -> String {
+        account_label(&self.hostname, self.account.as_ref().map(|a| &**a))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GrantSchema {
+    // schema < 0.2.0, plain user or group id
+    Id(String),
+    Grant(Grant),
+}
+
+fn deserialize_grants<'de, D>(deserializer: D) -> Result<Vec<Grant>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let grants: Vec<GrantSchema> = Deserialize::deserialize(deserializer)?;
+
+    Ok(grants
+        .into_iter()
+        .map(|g| match g {
+            GrantSchema::Id(id) => Grant {
+                id: id,
+                options: vec![],
+                expires_at: None,
+                account: None,
+            },
+            GrantSchema::Grant(grant) => grant,
+        })
+        .collect())
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct User {
     pub user_id: String,
-    pub public_key: String,
+
+    #[serde(default)]
+    pub public_keys: Vec<UserPublicKey>,
+
+    // schema < 0.2.0, migrated to public_keys on load
+    #[serde(default, skip_serializing)]
+    pub public_key: Option<String>,
+}
+
+impl User {
+    pub fn public_key_get(&self, label: &str) -> Option<&UserPublicKey> {
+        self.public_keys
+            .iter()
+            .position(|k| k.label == label)
+            .map(|i| &self.public_keys[i])
+    }
+
+    // compares the key data, the same key with another comment is the same key
+    pub fn has_public_key(&self, key: &str) -> bool {
+        let blob = match public_key::parse(key) {
+            Ok(k) => k.blob,
+            Err(_) => return self.public_keys.iter().any(|k| k.key == key),
+        };
+
+        self.public_keys.iter().any(|k| match public_key::parse(&k.key) {
+            Ok(k) => k.blob == blob,
+            Err(_) => false,
+        })
+    }
 }
 
 impl fmt::Display for User {
@@ -158,10 +799,27 @@ impl fmt::Display for User {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserPublicKey {
+    pub key: String,
+    pub label: String,
+    pub added_at: String,
+}
+
+impl fmt::Display for UserPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.key)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserGroup {
     pub group_id: String,
     pub members: Vec<String>,
+
+    // nested groups, their members are members of this group too
+    #[serde(default)]
+    pub member_groups: Vec<String>,
 }
 
 impl fmt::Display for UserGroup {
@@ -169,3 +827,36 @@ impl fmt::Display for UserGroup {
         write!(f, "{}", self.group_id)
     }
 }
+
+// hosts sharing grants, e.g. all web servers
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HostGroup {
+    pub group_id: String,
+    // hostnames of the members
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub authorized_users: Vec<Grant>,
+    #[serde(default)]
+    pub authorized_user_groups: Vec<Grant>,
+}
+
+impl HostGroup {
+    // accounts used by grants of the host group, members need to have them
+    pub fn accounts(&self) -> Vec<&str> {
+        let mut accounts: Vec<&str> = self
+            .authorized_users
+            .iter()
+            .chain(self.authorized_user_groups.iter())
+            .filter_map(|g| g.account.as_ref().map(|a| &**a))
+            .collect();
+        accounts.sort();
+        accounts.dedup();
+        accounts
+    }
+}
+
+impl fmt::Display for HostGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.group_id)
+    }
+}
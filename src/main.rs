@@ -1,26 +1,142 @@
 #[macro_use]
 extern crate serde_derive;
 
+extern crate base64;
 extern crate chrono;
 extern crate clap;
 extern crate colored;
 extern crate difference;
+extern crate libc;
+extern crate md5;
 extern crate rpassword;
 extern crate serde;
 extern crate serde_json;
+extern crate sha2;
 extern crate ssh2;
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::error::Error;
 use std::path::Path;
 
+mod authorized_keys;
+mod backup;
 mod cli_flow;
+mod connection;
 mod database;
+mod public_key;
 mod ssh_config;
+mod subcommand_access;
+mod subcommand_audit;
+mod subcommand_check;
+mod subcommand_expired;
 mod subcommand_group;
 mod subcommand_host;
+mod subcommand_hostgroup;
 mod subcommand_howto;
 mod subcommand_sync;
 mod subcommand_user;
+mod tunnel;
+
+// connection arguments of sync and check
+fn connect_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        // --password
+        Arg::with_name("password")
+            .short("p")
+            .long("password")
+            .help("Use password authentication instead of public key")
+            .takes_value(false),
+        // --yes-authorized-keys-prompt
+        Arg::with_name("yes_authorized_keys_prompt")
+            .short("yakp")
+            .long("yes-authorized-keys-prompt")
+            .help("Automatic yes to authorized_keys location prompts")
+            .takes_value(false),
+        // --non-interactive
+        Arg::with_name("non_interactive")
+            .long("non-interactive")
+            .help("Never prompt, fail if information is missing")
+            .takes_value(false),
+        // --ssh-user
+        Arg::with_name("ssh_user")
+            .long("ssh-user")
+            .value_name("USER")
+            .help("SSH user for all hosts, overrides User of ssh_config")
+            .takes_value(true),
+        // --identity
+        Arg::with_name("identity")
+            .short("i")
+            .long("identity")
+            .value_name("FILE")
+            .help("Private key file used if SSH agent authentication fails. Default: IdentityFile of ssh_config or ~/.ssh/id_ed25519, id_ecdsa, id_rsa")
+            .takes_value(true),
+        // --accept-new
+        Arg::with_name("accept_new")
+            .long("accept-new")
+            .help("Add unknown host keys to known_hosts without asking. Changed keys are still refused")
+            .takes_value(false),
+        // --authorized-keys-path
+        Arg::with_name("authorized_keys_path")
+            .long("authorized-keys-path")
+            .value_name("PATH")
+            .help("Remote authorized_keys file. Default: $HOME/.ssh/authorized_keys")
+            .takes_value(true),
+    ]
+}
+
+fn connect_options(matches: &ArgMatches) -> connection::ConnectOptions {
+    connection::ConnectOptions {
+        password_auth: matches.is_present("password"),
+        yes_authorized_keys_prompt: matches.is_present("yes_authorized_keys_prompt"),
+        non_interactive: matches.is_present("non_interactive"),
+        ssh_user: matches.value_of("ssh_user").map(|u| u.to_owned()),
+        identity: matches.value_of("identity").map(|i| i.to_owned()),
+        authorized_keys_path: matches.value_of("authorized_keys_path").map(|p| p.to_owned()),
+        accept_new: matches.is_present("accept_new"),
+        account: None,
+    }
+}
+
+// local backups are kept next to the database
+fn backup_options(db: &database::Database, database_file: &str) -> backup::BackupOptions {
+    let database_dir = Path::new(database_file)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .to_path_buf();
+
+    backup::BackupOptions {
+        dir: database_dir.join("ssh-permit-backups"),
+        retention: db.backup_retention,
+    }
+}
+
+// --as of grant, revoke and restore
+fn account_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("as")
+        .long("as")
+        .value_name("ACCOUNT")
+        .help("Remote account of the host, see host <host> account. Default: the account chosen on sync")
+        .takes_value(true)
+}
+
+// --until / --for of user and group grant
+fn grant_expires_at(matches: &ArgMatches) -> Option<String> {
+    let expires_at = if let Some(until) = matches.value_of("until") {
+        database::Grant::expires_at_until(until)
+    } else if let Some(duration) = matches.value_of("for") {
+        database::Grant::expires_at_for(duration)
+    } else {
+        return None;
+    };
+
+    match expires_at {
+        Ok(e) => Some(e),
+        Err(e) => {
+            cli_flow::errorln(&e.to_string());
+            None
+        }
+    }
+}
 
 fn main() {
     let matches = App::new("SSH Permit A38")
@@ -76,6 +192,87 @@ fn main() {
                             .index(1)
                             .required(false))
                 )
+                // host <host> managed-block <on|off>
+                .subcommand(
+                    SubCommand::with_name("managed-block")
+                        .about("Only manage the lines between # BEGIN ssh-permit-a38 and # END ssh-permit-a38 in authorized_keys")
+                        .arg(Arg::with_name("state")
+                            .help("on|off")
+                            .index(1)
+                            .possible_values(&["on", "off"])
+                            .required(true))
+                )
+                // host <host> access
+                .subcommand(
+                    SubCommand::with_name("access")
+                        .about("Lists the users able to log into the host and the grants they come from")
+                )
+                // host <host> account
+                .subcommand(
+                    SubCommand::with_name("account")
+                        .about("Remote accounts with an authorized_keys of their own, e.g. deploy and root")
+                        .alias("accounts")
+                        // host <host> account add <account>
+                        .subcommand(
+                            SubCommand::with_name("add")
+                                .arg(Arg::with_name("account")
+                                    .help("Account")
+                                    .index(1)
+                                    .required(true))
+                        )
+                        // host <host> account remove <account>
+                        .subcommand(
+                            SubCommand::with_name("remove")
+                                .arg(Arg::with_name("account")
+                                    .help("Account")
+                                    .index(1)
+                                    .required(true))
+                        )
+                )
+                // host <host> proxy-jump <[user@]host[:port][,...]>
+                .subcommand(
+                    SubCommand::with_name("proxy-jump")
+                        .about("Connects through jump hosts like ProxyJump of ssh_config, none disables ProxyJump of ssh_config. Without jump hosts: removes them")
+                        .arg(Arg::with_name("jump")
+                            .help("[user@]host[:port], comma separated for multiple hops")
+                            .index(1)
+                            .required(false))
+                )
+                // host <host> trust [--replace] [--yes]
+                .subcommand(
+                    SubCommand::with_name("trust")
+                        .about("Pins the host key presented by the host, verified on sync instead of known_hosts")
+                        .args(&connect_args())
+                        // --replace
+                        .arg(Arg::with_name("replace")
+                            .long("replace")
+                            .help("Replace a pinned host key that differs")
+                            .takes_value(false))
+                        // --yes
+                        .arg(Arg::with_name("yes")
+                            .long("yes")
+                            .help("Automatic yes to trust confirmation prompt")
+                            .takes_value(false))
+                )
+                // host <host> restore [--backup <id>]
+                .subcommand(
+                    SubCommand::with_name("restore")
+                        .about("Restores a backup of authorized_keys saved by sync. Default: the latest")
+                        .args(&connect_args())
+                        // --as
+                        .arg(account_arg())
+                        // --backup
+                        .arg(Arg::with_name("backup")
+                            .long("backup")
+                            .value_name("ID")
+                            .help("Backup id, e.g. 20180818T120000.123456Z")
+                            .takes_value(true))
+                        // --yes
+                        .arg(Arg::with_name("yes")
+                            .long("yes")
+                            .help("Automatic yes to restore confirmation prompt")
+                            .takes_value(false))
+                )
         )
 
         // user
@@ -110,17 +307,83 @@ fn main() {
                 .subcommand(
                     SubCommand::with_name("grant")
                         .arg(Arg::with_name("host")
-                            .help("Host")
+                            .help("Host or host group")
                             .index(1)
                             .required(true))
+                        // --as
+                        .arg(account_arg())
+                        // --option
+                        .arg(Arg::with_name("option")
+                            .short("o")
+                            .long("option")
+                            .value_name("OPTION")
+                            .help("authorized_keys option, e.g. from=10.0.0.0/8, no-pty")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1))
+                        // --until
+                        .arg(Arg::with_name("until")
+                            .long("until")
+                            .value_name("DATE")
+                            .help("Grant expires at DATE, e.g. 2018-08-18 or 2018-08-18T12:00:00Z")
+                            .takes_value(true)
+                            .conflicts_with("for"))
+                        // --for
+                        .arg(Arg::with_name("for")
+                            .long("for")
+                            .value_name("DURATION")
+                            .help("Grant expires after DURATION, e.g. 30m, 12h, 7d or 2w")
+                            .takes_value(true))
                 )
                 // user <user> grant <host>
                 .subcommand(
                     SubCommand::with_name("revoke")
                         .arg(Arg::with_name("host")
-                            .help("Host")
+                            .help("Host or host group")
                             .index(1)
                             .required(true))
+                        // --as
+                        .arg(account_arg())
+                )
+                // user <user> access
+                .subcommand(
+                    SubCommand::with_name("access")
+                        .about("Lists the hosts the user can log into and the grants they come from")
+                )
+                // user find-by-fingerprint <fingerprint>
+                .subcommand(
+                    SubCommand::with_name("find-by-fingerprint")
+                        .about("Finds the user of a public key fingerprint (SHA256:... or MD5:...)")
+                        .arg(Arg::with_name("fingerprint")
+                            .help("Fingerprint")
+                            .index(1)
+                            .required(true))
+                )
+                // user <user> key
+                .subcommand(
+                    SubCommand::with_name("key")
+                        .about("Public key related actions")
+                        .alias("keys")
+                        // user <user> key add <label>
+                        .subcommand(
+                            SubCommand::with_name("add")
+                                .arg(Arg::with_name("label")
+                                    .help("Key label")
+                                    .index(1)
+                                    .required(true))
+                        )
+                        // user <user> key remove <label>
+                        .subcommand(
+                            SubCommand::with_name("remove")
+                                .arg(Arg::with_name("label")
+                                    .help("Key label")
+                                    .index(1)
+                                    .required(true))
+                        )
+                        // user <user> key list
+                        .subcommand(
+                            SubCommand::with_name("list")
+                        )
                 )
         )
 
@@ -152,6 +415,23 @@ fn main() {
                         .index(1)
                         .required(false))
                 )
+                // group <group> add-group <group>
+                .subcommand(
+                    SubCommand::with_name("add-group")
+                        .about("Nests a group, its members become members of this group too")
+                        .arg(Arg::with_name("member_group")
+                        .help("Group")
+                        .index(1)
+                        .required(true))
+                )
+                // group <group> remove-group <group>
+                .subcommand(
+                    SubCommand::with_name("remove-group")
+                        .arg(Arg::with_name("member_group")
+                        .help("Group")
+                        .index(1)
+                        .required(true))
+                )
                 // group list
                 .subcommand(
                     SubCommand::with_name("list")
@@ -167,13 +447,85 @@ fn main() {
                 .subcommand(
                     SubCommand::with_name("grant")
                         .arg(Arg::with_name("host")
-                            .help("Host")
+                            .help("Host or host group")
                             .index(1)
                             .required(true))
+                        // --as
+                        .arg(account_arg())
+                        // --option
+                        .arg(Arg::with_name("option")
+                            .short("o")
+                            .long("option")
+                            .value_name("OPTION")
+                            .help("authorized_keys option, e.g. from=10.0.0.0/8, no-pty")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1))
+                        // --until
+                        .arg(Arg::with_name("until")
+                            .long("until")
+                            .value_name("DATE")
+                            .help("Grant expires at DATE, e.g. 2018-08-18 or 2018-08-18T12:00:00Z")
+                            .takes_value(true)
+                            .conflicts_with("for"))
+                        // --for
+                        .arg(Arg::with_name("for")
+                            .long("for")
+                            .value_name("DURATION")
+                            .help("Grant expires after DURATION, e.g. 30m, 12h, 7d or 2w")
+                            .takes_value(true))
                 )
                 // group <group> revoke <host>
                 .subcommand(
                     SubCommand::with_name("revoke")
+                        .arg(Arg::with_name("host")
+                            .help("Host or host group")
+                            .index(1)
+                            .required(true))
+                        // --as
+                        .arg(account_arg())
+                )
+        )
+
+        // hostgroup
+        .subcommand(
+            SubCommand::with_name("hostgroup")
+                // hostgroup <hostgroup>
+                .about("Host group related actions, user and group grants to a host group apply to all its hosts")
+                .arg(Arg::with_name("hostgroup")
+                .help("Host group")
+                .index(1))
+                .alias("hostgroups")
+
+                // hostgroup <hostgroup> add
+                .subcommand(
+                    SubCommand::with_name("add")
+                )
+                // hostgroup <hostgroup> remove
+                .subcommand(
+                    SubCommand::with_name("remove")
+                )
+                // hostgroup list
+                .subcommand(
+                    SubCommand::with_name("list")
+                    // --raw
+                    .arg(
+                        Arg::with_name("raw")
+                            .short("r")
+                            .long("raw")
+                            .help("Prints raw host group struct"))
+                )
+                // hostgroup <hostgroup> add-host <host>
+                .subcommand(
+                    SubCommand::with_name("add-host")
+                        .arg(Arg::with_name("host")
+                            .help("Host")
+                            .index(1)
+                            .required(true))
+                )
+                // hostgroup <hostgroup> remove-host <host>
+                .subcommand(
+                    SubCommand::with_name("remove-host")
                         .arg(Arg::with_name("host")
                             .help("Host")
                             .index(1)
@@ -185,23 +537,72 @@ fn main() {
         .subcommand(
             SubCommand::with_name("sync")
                 .about("Sync pending changes to the related hosts")
-                // --password
+                .args(&connect_args())
+                // --yes
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .help("Automatic yes to sync confirmation prompts")
+                        .takes_value(false),
+                )
+                // --dry-run
                 .arg(
-                    Arg::with_name("password")
-                        .short("p")
-                        .long("password")
-                        .help("Use password authentication instead of public key")
+                    Arg::with_name("dry_run")
+                        .short("n")
+                        .long("dry-run")
+                        .help("Only show the changes, don't sync")
                         .takes_value(false),
                 )
-                // --yes-authorized-keys-prompt
+                // --allow-lockout
+                .arg(
+                    Arg::with_name("allow_lockout")
+                        .long("allow-lockout")
+                        .help("Sync even if the key used to connect is removed from authorized_keys")
+                        .takes_value(false),
+                )
+                // --jobs
+                .arg(
+                    Arg::with_name("jobs")
+                        .short("j")
+                        .long("jobs")
+                        .value_name("N")
+                        .help("Number of hosts synced concurrently, requires --non-interactive. Default: 1")
+                        .takes_value(true),
+                )
+        )
+        // check
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Compares the authorized_keys of all hosts with the database, exits non-zero on drift")
+                .args(&connect_args())
+        )
+        // audit
+        .subcommand(
+            SubCommand::with_name("audit")
+                .about("Checks the database against policies")
+                // audit keys
+                .subcommand(
+                    SubCommand::with_name("keys")
+                        .about("Lists public keys violating the key policy")
+                )
+        )
+        // access
+        .subcommand(
+            SubCommand::with_name("access")
+                .about("Lists the effective access of all users to all hosts")
+                // --csv
                 .arg(
-                    Arg::with_name("yes_authorized_keys_prompt")
-                        .short("yakp")
-                        .long("yes-authorized-keys-prompt")
-                        .help("Automatic yes to authorized_keys location prompts")
+                    Arg::with_name("csv")
+                        .long("csv")
+                        .help("Exports a user x host matrix as CSV, one column per account of a host")
                         .takes_value(false),
                 )
         )
+        // expired
+        .subcommand(
+            SubCommand::with_name("expired")
+                .about("Lists expired grants not yet synced away")
+        )
         // howto
         .subcommand(
             SubCommand::with_name("howto")
@@ -235,6 +636,9 @@ fn main() {
         ));
     }
 
+    // error reported after the database is saved
+    let mut r_sync: Result<(), Box<Error>> = Ok(());
+
     // host
     if let Some(matches) = matches.subcommand_matches("host") {
         let hostname = matches.value_of("host:port").unwrap_or("");
@@ -247,6 +651,43 @@ fn main() {
             subcommand_host::list(&mut db, &hostname, matches.is_present("raw"));
         } else if let Some(matches) = matches.subcommand_matches("alias") {
             subcommand_host::alias(&mut db, &hostname, matches.value_of("alias"));
+        } else if let Some(matches) = matches.subcommand_matches("managed-block") {
+            subcommand_host::managed_block(
+                &mut db,
+                &hostname,
+                matches.value_of("state") == Some("on"),
+            );
+        } else if matches.subcommand_matches("access").is_some() {
+            subcommand_access::host(&mut db, &hostname);
+        } else if let Some(matches) = matches.subcommand_matches("account") {
+            if let Some(matches) = matches.subcommand_matches("add") {
+                let account = matches.value_of("account").unwrap();
+                subcommand_host::account_add(&mut db, &hostname, &account);
+            } else if let Some(matches) = matches.subcommand_matches("remove") {
+                let account = matches.value_of("account").unwrap();
+                subcommand_host::account_remove(&mut db, &hostname, &account);
+            }
+        } else if let Some(matches) = matches.subcommand_matches("proxy-jump") {
+            subcommand_host::proxy_jump(&mut db, &hostname, matches.value_of("jump"));
+        } else if let Some(matches) = matches.subcommand_matches("trust") {
+            subcommand_host::trust(
+                &mut db,
+                &hostname,
+                matches.is_present("replace"),
+                &connect_options(&matches),
+                matches.is_present("yes"),
+            );
+        } else if let Some(matches) = matches.subcommand_matches("restore") {
+            let backup_options = backup_options(&db, &database_file);
+            subcommand_host::restore(
+                &mut db,
+                &hostname,
+                matches.value_of("as"),
+                matches.value_of("backup"),
+                &connect_options(&matches),
+                &backup_options,
+                matches.is_present("yes"),
+            );
         }
     }
     // user
@@ -261,10 +702,34 @@ fn main() {
             subcommand_user::list(&mut db, &user_id, matches.is_present("raw"));
         } else if let Some(matches) = matches.subcommand_matches("grant") {
             let hostname = matches.value_of("host").unwrap();
-            subcommand_user::grant(&mut db, &user_id, &hostname);
+            let options: Vec<&str> = matches.values_of("option").unwrap_or_default().collect();
+            let expires_at = grant_expires_at(&matches);
+            subcommand_user::grant(
+                &mut db,
+                &user_id,
+                &hostname,
+                matches.value_of("as"),
+                &options,
+                expires_at,
+            );
         } else if let Some(matches) = matches.subcommand_matches("revoke") {
             let hostname = matches.value_of("host").unwrap();
-            subcommand_user::revoke(&mut db, &user_id, &hostname);
+            subcommand_user::revoke(&mut db, &user_id, &hostname, matches.value_of("as"));
+        } else if matches.subcommand_matches("access").is_some() {
+            subcommand_access::user(&mut db, &user_id);
+        } else if let Some(matches) = matches.subcommand_matches("find-by-fingerprint") {
+            let fingerprint = matches.value_of("fingerprint").unwrap();
+            subcommand_user::find_by_fingerprint(&mut db, &fingerprint);
+        } else if let Some(matches) = matches.subcommand_matches("key") {
+            if let Some(matches) = matches.subcommand_matches("add") {
+                let label = matches.value_of("label").unwrap();
+                subcommand_user::key_add(&mut db, &user_id, &label);
+            } else if let Some(matches) = matches.subcommand_matches("remove") {
+                let label = matches.value_of("label").unwrap();
+                subcommand_user::key_remove(&mut db, &user_id, &label);
+            } else if matches.subcommand_matches("list").is_some() {
+                subcommand_user::key_list(&mut db, &user_id);
+            }
         }
     }
     // group
@@ -281,24 +746,91 @@ fn main() {
                 Some(user_id) => subcommand_group::user_remove(&mut db, &group_id, &user_id),
                 None => subcommand_group::remove(&mut db, &group_id),
             }
+        } else if let Some(matches) = matches.subcommand_matches("add-group") {
+            let member_group_id = matches.value_of("member_group").unwrap();
+            subcommand_group::group_add(&mut db, &group_id, &member_group_id);
+        } else if let Some(matches) = matches.subcommand_matches("remove-group") {
+            let member_group_id = matches.value_of("member_group").unwrap();
+            subcommand_group::group_remove(&mut db, &group_id, &member_group_id);
         } else if let Some(matches) = matches.subcommand_matches("list") {
             subcommand_group::list(&mut db, &group_id, matches.is_present("raw"));
         } else if let Some(matches) = matches.subcommand_matches("grant") {
             let hostname = matches.value_of("host").unwrap();
-            subcommand_group::grant(&mut db, &group_id, &hostname);
+            let options: Vec<&str> = matches.values_of("option").unwrap_or_default().collect();
+            let expires_at = grant_expires_at(&matches);
+            subcommand_group::grant(
+                &mut db,
+                &group_id,
+                &hostname,
+                matches.value_of("as"),
+                &options,
+                expires_at,
+            );
         } else if let Some(matches) = matches.subcommand_matches("revoke") {
             let hostname = matches.value_of("host").unwrap();
-            subcommand_group::revoke(&mut db, &group_id, &hostname);
+            subcommand_group::revoke(&mut db, &group_id, &hostname, matches.value_of("as"));
+        }
+    }
+    // hostgroup
+    else if let Some(matches) = matches.subcommand_matches("hostgroup") {
+        let group_id = matches.value_of("hostgroup").unwrap_or("");
+
+        if matches.subcommand_matches("add").is_some() {
+            subcommand_hostgroup::add(&mut db, &group_id);
+        } else if matches.subcommand_matches("remove").is_some() {
+            subcommand_hostgroup::remove(&mut db, &group_id);
+        } else if let Some(matches) = matches.subcommand_matches("list") {
+            subcommand_hostgroup::list(&mut db, &group_id, matches.is_present("raw"));
+        } else if let Some(matches) = matches.subcommand_matches("add-host") {
+            let hostname = matches.value_of("host").unwrap();
+            subcommand_hostgroup::host_add(&mut db, &group_id, &hostname);
+        } else if let Some(matches) = matches.subcommand_matches("remove-host") {
+            let hostname = matches.value_of("host").unwrap();
+            subcommand_hostgroup::host_remove(&mut db, &group_id, &hostname);
         }
     }
     // sync
     else if let Some(matches) = matches.subcommand_matches("sync") {
-        subcommand_sync::sync(
+        let jobs = match matches.value_of("jobs").unwrap_or("1").parse::<usize>() {
+            Ok(j) if j > 0 => j,
+            _ => {
+                cli_flow::errorln("--jobs expects a number greater than 0");
+                0
+            }
+        };
+
+        let backup_options = backup_options(&db, &database_file);
+
+        r_sync = subcommand_sync::sync(
             &mut db,
-            matches.is_present("password"),
-            matches.is_present("yes_authorized_keys_prompt"),
+            &subcommand_sync::SyncOptions {
+                connect: connect_options(&matches),
+                yes: matches.is_present("yes"),
+                dry_run: matches.is_present("dry_run"),
+                jobs: jobs,
+                backup: backup_options,
+                allow_lockout: matches.is_present("allow_lockout"),
+            },
         );
     }
+    // check
+    else if let Some(matches) = matches.subcommand_matches("check") {
+        subcommand_check::check(&db, &connect_options(&matches));
+    }
+    // audit
+    else if let Some(matches) = matches.subcommand_matches("audit") {
+        if matches.subcommand_matches("keys").is_some() {
+            subcommand_audit::keys(&mut db);
+        }
+    }
+    // access
+    else if let Some(matches) = matches.subcommand_matches("access") {
+        subcommand_access::list(&mut db, matches.is_present("csv"));
+    }
+    // expired
+    else if matches.subcommand_matches("expired").is_some() {
+        subcommand_expired::list(&mut db);
+    }
     // howto
     else if matches.subcommand_matches("howto").is_some() {
         subcommand_howto::print();
@@ -306,4 +838,9 @@ fn main() {
 
     // save database
     db.save(&database_file);
+
+    // state of synced hosts is saved, now report failed ones
+    if let Err(e) = r_sync {
+        cli_flow::errorln(&e.to_string());
+    }
 }
@@ -0,0 +1,448 @@
+use base64;
+use md5;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Rsa,
+    Dss,
+    Ed25519,
+    EcdsaNistp256,
+    EcdsaNistp384,
+    EcdsaNistp521,
+    SkEcdsaNistp256,
+    SkEd25519,
+}
+
+impl Algorithm {
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name {
+            "ssh-rsa" => Some(Algorithm::Rsa),
+            "ssh-dss" => Some(Algorithm::Dss),
+            "ssh-ed25519" => Some(Algorithm::Ed25519),
+            "ecdsa-sha2-nistp256" => Some(Algorithm::EcdsaNistp256),
+            "ecdsa-sha2-nistp384" => Some(Algorithm::EcdsaNistp384),
+            "ecdsa-sha2-nistp521" => Some(Algorithm::EcdsaNistp521),
+            "sk-ecdsa-sha2-nistp256@openssh.com" => Some(Algorithm::SkEcdsaNistp256),
+            "sk-ssh-ed25519@openssh.com" => Some(Algorithm::SkEd25519),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Algorithm::Rsa => "ssh-rsa",
+            Algorithm::Dss => "ssh-dss",
+            Algorithm::Ed25519 => "ssh-ed25519",
+            Algorithm::EcdsaNistp256 => "ecdsa-sha2-nistp256",
+            Algorithm::EcdsaNistp384 => "ecdsa-sha2-nistp384",
+            Algorithm::EcdsaNistp521 => "ecdsa-sha2-nistp521",
+            Algorithm::SkEcdsaNistp256 => "sk-ecdsa-sha2-nistp256@openssh.com",
+            Algorithm::SkEd25519 => "sk-ssh-ed25519@openssh.com",
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug)]
+pub struct PublicKey {
+    pub algorithm: Algorithm,
+    pub bits: usize,
+    pub comment: String,
+    pub blob: Vec<u8>,
+}
+
+impl PublicKey {
+    // e.g. SHA256:a5lcfVLkzdMXMaOPcon5tzlRKbyidKwb6TIZpBUcidM like ssh-keygen -lf
+    pub fn fingerprint_sha256(&self) -> String {
+        let mut hasher = Sha256::default();
+        hasher.input(&self.blob);
+        format!(
+            "SHA256:{}",
+            base64::encode_config(&hasher.result(), base64::STANDARD_NO_PAD)
+        )
+    }
+
+    // e.g. MD5:2b:fa:67:c8:0f:86:b9:0f:87:9e:7e:fc:61:3d:b9:c0 like ssh-keygen -E md5 -lf
+    pub fn fingerprint_md5(&self) -> String {
+        let digest = md5::compute(&self.blob);
+        format!(
+            "MD5:{}",
+            digest
+                .0
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<String>>()
+                .join(":")
+        )
+    }
+
+    pub fn matches_fingerprint(&self, fingerprint: &str) -> bool {
+        let fingerprint = fingerprint.trim();
+        let sha256 = self.fingerprint_sha256();
+        let md5 = self.fingerprint_md5();
+
+        // prefix is optional, MD5 hex is case insensitive
+        fingerprint == sha256
+            || fingerprint == &sha256["SHA256:".len()..]
+            || fingerprint.to_lowercase() == md5.to_lowercase()
+            || fingerprint.to_lowercase() == &md5["MD5:".len()..]
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.algorithm, base64::encode(&self.blob))?;
+        if !self.comment.is_empty() {
+            write!(f, " {}", self.comment)?;
+        }
+        Ok(())
+    }
+}
+
+// reader for the SSH wire format (RFC 4251 section 5)
+struct WireReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> WireReader<'a> {
+    fn read_string(&mut self) -> Result<&'a [u8], Box<Error>> {
+        if self.buf.len() < 4 {
+            return Err(From::from("Public key data truncated"));
+        }
+
+        let len = ((self.buf[0] as usize) << 24)
+            | ((self.buf[1] as usize) << 16)
+            | ((self.buf[2] as usize) << 8)
+            | (self.buf[3] as usize);
+
+        if self.buf.len() - 4 < len {
+            return Err(From::from("Public key data truncated"));
+        }
+
+        let value = &self.buf[4..4 + len];
+        self.buf = &self.buf[4 + len..];
+        Ok(value)
+    }
+
+    // bit length of a mpint, ignoring leading zero bytes
+    fn read_mpint_bits(&mut self) -> Result<usize, Box<Error>> {
+        let mpint = self.read_string()?;
+        match mpint.iter().position(|b| *b != 0) {
+            Some(i) => Ok((mpint.len() - i) * 8 - mpint[i].leading_zeros() as usize),
+            None => Ok(0),
+        }
+    }
+
+    fn read_curve(&mut self, expected: &str) -> Result<(), Box<Error>> {
+        let curve = self.read_string()?;
+        if curve != expected.as_bytes() {
+            return Err(From::from(format!(
+                "Public key curve {} does not match {}",
+                String::from_utf8_lossy(curve),
+                expected
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn parse(public_key: &str) -> Result<PublicKey, Box<Error>> {
+    let mut parts = public_key.trim().splitn(2, char::is_whitespace);
+
+    let algorithm_name = parts.next().unwrap_or("");
+    let algorithm = match Algorithm::from_name(algorithm_name) {
+        Some(a) => a,
+        None => {
+            return Err(From::from(format!(
+                "Unsupported public key algorithm \"{}\"",
+                algorithm_name
+            )))
+        }
+    };
+
+    let mut parts = parts
+        .next()
+        .unwrap_or("")
+        .trim_left()
+        .splitn(2, char::is_whitespace);
+
+    let blob = match base64::decode(parts.next().unwrap_or("")) {
+        Ok(b) => b,
+        Err(e) => {
+            return Err(From::from(format!(
+                "Public key data is not valid base64 - {}",
+                e.to_string()
+            )))
+        }
+    };
+
+    let comment = parts.next().unwrap_or("").trim().to_owned();
+
+    blob_parse(algorithm, blob, comment)
+}
+
+// public key from its SSH wire format, e.g. a host key
+pub fn from_blob(blob: &[u8]) -> Result<PublicKey, Box<Error>> {
+    let algorithm_name = WireReader { buf: blob }.read_string()?;
+    let algorithm = match Algorithm::from_name(&String::from_utf8_lossy(algorithm_name)) {
+        Some(a) => a,
+        None => {
+            return Err(From::from(format!(
+                "Unsupported public key algorithm \"{}\"",
+                String::from_utf8_lossy(algorithm_name)
+            )))
+        }
+    };
+
+    blob_parse(algorithm, blob.to_vec(), String::new())
+}
+
+fn blob_parse(algorithm: Algorithm, blob: Vec<u8>, comment: String) -> Result<PublicKey, Box<Error>> {
+    // embedded algorithm has to match the prefix
+    let mut reader = WireReader { buf: &blob };
+    let blob_algorithm_name = reader.read_string()?;
+    if blob_algorithm_name != algorithm.name().as_bytes() {
+        return Err(From::from(format!(
+            "Public key data is of type {} but prefixed with {}",
+            String::from_utf8_lossy(blob_algorithm_name),
+            algorithm
+        )));
+    }
+
+    let bits = match algorithm {
+        Algorithm::Rsa => {
+            // e, n
+            reader.read_mpint_bits()?;
+            reader.read_mpint_bits()?
+        }
+        Algorithm::Dss => {
+            // p, q, g, y
+            let bits = reader.read_mpint_bits()?;
+            reader.read_mpint_bits()?;
+            reader.read_mpint_bits()?;
+            reader.read_mpint_bits()?;
+            bits
+        }
+        Algorithm::Ed25519 | Algorithm::SkEd25519 => {
+            if reader.read_string()?.len() != 32 {
+                return Err(From::from("Invalid ed25519 public key length"));
+            }
+            if algorithm == Algorithm::SkEd25519 {
+                // application
+                reader.read_string()?;
+            }
+            256
+        }
+        Algorithm::EcdsaNistp256 | Algorithm::SkEcdsaNistp256 => {
+            reader.read_curve("nistp256")?;
+            reader.read_string()?;
+            if algorithm == Algorithm::SkEcdsaNistp256 {
+                // application
+                reader.read_string()?;
+            }
+            256
+        }
+        Algorithm::EcdsaNistp384 => {
+            reader.read_curve("nistp384")?;
+            reader.read_string()?;
+            384
+        }
+        Algorithm::EcdsaNistp521 => {
+            reader.read_curve("nistp521")?;
+            reader.read_string()?;
+            521
+        }
+    };
+
+    if !reader.buf.is_empty() {
+        return Err(From::from("Unexpected trailing public key data"));
+    }
+
+    Ok(PublicKey {
+        algorithm: algorithm,
+        bits: bits,
+        comment: comment,
+        blob: blob,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fingerprints and bits of ssh-keygen -lf and ssh-keygen -E md5 -lf
+    const PUBLIC_KEY_ED25519: &'static str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAINZ4NANG2PI6O7Ldl7NusZCRgYZ+jMwH8cEE47wpqBKc foo1@example.com";
+    const PUBLIC_KEY_RSA: &'static str = concat!(
+        "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQCk0QrfEBBWs3b5KiXYOO4VbWJVNad0zw/pADV/fRRpaca/Z03F",
+        "YGfeRHmPXLamyY1AA4We0tyXowV5rSAJGjO6GBDBylDG/YOrIVYoFtzp9yOa2Ghv+Zvq8gmPp1Gaz/yB3N/mnHb+",
+        "z4pWB4o4+QJ1NnGv/vfLsLlkhg24d0gsRcB9VlQOVtfe8jjUasmYpkG34aqcFscyWTG8w25k5S4NMhe1jJJfkTZX",
+        "rPKABh5n7O5nHfUZrhn6RM8fyJZnzcMoOqTkg8E4JC+Am9P/1riTLfL6iZf5U9AnuUT4oeAVx1WVZW2jRUyEJyli",
+        "hfvF7SImbSkk0740oTIVVqxYM57R rsa@example.com"
+    );
+    const PUBLIC_KEY_ECDSA: &'static str = concat!(
+        "ecdsa-sha2-nistp384 AAAAE2VjZHNhLXNoYTItbmlzdHAzODQAAAAIbmlzdHAzODQAAABhBFiLrbQ/mVHkq0gj",
+        "Il/hHXTj82g8w072s/TcbaZmakl3DYPeOe0TTYes4oooH/k5/r29JJpvtiqCri/c6jYidSryH4OwlYgqhbaOFmJ5",
+        "2dZCuNR+jiyhduepUlM9cLzdfw== ecdsa@example.com"
+    );
+
+    fn blob(public_key: &str) -> Vec<u8> {
+        base64::decode(public_key.split_whitespace().nth(1).unwrap()).unwrap()
+    }
+
+    fn parse_err(public_key: &str) -> String {
+        parse(public_key).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parse_known_keys() {
+        let keys = [
+            (
+                PUBLIC_KEY_ED25519,
+                Algorithm::Ed25519,
+                256,
+                "SHA256:a5lcfVLkzdMXMaOPcon5tzlRKbyidKwb6TIZpBUcidM",
+                "MD5:2b:fa:67:c8:0f:86:b9:0f:87:9e:7e:fc:61:3d:b9:c0",
+                "foo1@example.com",
+            ),
+            (
+                PUBLIC_KEY_RSA,
+                Algorithm::Rsa,
+                2048,
+                "SHA256:dbFQJWxF54jHnI+BBogvpK8ZkFmx4hDnzfYPdKchMO0",
+                "MD5:75:6a:e5:f0:2a:44:0b:05:70:a3:48:74:39:d4:d9:fe",
+                "rsa@example.com",
+            ),
+            (
+                PUBLIC_KEY_ECDSA,
+                Algorithm::EcdsaNistp384,
+                384,
+                "SHA256:p/z9peb7Jy3RKun8yKz89PPXYZLkfqYdoBymvvBWyA0",
+                "MD5:9b:0d:4b:09:e3:5b:24:f9:c4:09:a7:76:7b:f8:87:c7",
+                "ecdsa@example.com",
+            ),
+        ];
+
+        for &(public_key, algorithm, bits, sha256, md5, comment) in keys.iter() {
+            let key = parse(public_key).unwrap();
+
+            assert_eq!(key.algorithm, algorithm);
+            assert_eq!(key.bits, bits);
+            assert_eq!(key.fingerprint_sha256(), sha256);
+            assert_eq!(key.fingerprint_md5(), md5);
+            assert_eq!(key.comment, comment);
+            assert_eq!(key.blob, blob(public_key));
+            assert_eq!(key.to_string(), public_key);
+        }
+    }
+
+    #[test]
+    fn parse_comment() {
+        let key = parse(&format!("  {}  ", PUBLIC_KEY_ED25519.replace(" foo1", "\tfoo1"))).unwrap();
+        assert_eq!(key.comment, "foo1@example.com");
+
+        let key =
+            parse(&PUBLIC_KEY_ED25519.replace("foo1@example.com", "Foo One (laptop)")).unwrap();
+        assert_eq!(key.comment, "Foo One (laptop)");
+
+        let key = parse(&PUBLIC_KEY_ED25519.replace(" foo1@example.com", "")).unwrap();
+        assert_eq!(key.comment, "");
+    }
+
+    #[test]
+    fn from_blob_matches_parse() {
+        for public_key in [PUBLIC_KEY_ED25519, PUBLIC_KEY_RSA, PUBLIC_KEY_ECDSA].iter() {
+            let key = from_blob(&blob(public_key)).unwrap();
+            let parsed = parse(public_key).unwrap();
+
+            assert_eq!(key.algorithm, parsed.algorithm);
+            assert_eq!(key.bits, parsed.bits);
+            assert_eq!(key.fingerprint_sha256(), parsed.fingerprint_sha256());
+            assert_eq!(key.comment, "");
+        }
+
+        assert!(from_blob(&[]).is_err());
+    }
+
+    #[test]
+    fn mpint_bits() {
+        // leading zero byte of a positive mpint and leading zero bits of the first byte
+        let mut reader = WireReader {
+            buf: &[0, 0, 0, 3, 0x00, 0x35, 0xff, 0, 0, 0, 1, 0x01, 0, 0, 0, 0],
+        };
+
+        assert_eq!(reader.read_mpint_bits().unwrap(), 14);
+        assert_eq!(reader.read_mpint_bits().unwrap(), 1);
+        assert_eq!(reader.read_mpint_bits().unwrap(), 0);
+        assert!(reader.read_mpint_bits().is_err());
+    }
+
+    #[test]
+    fn parse_malformed_base64() {
+        assert!(
+            parse_err("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAINZ4NANG2PI6O7Ldl7Nus!!")
+                .contains("not valid base64")
+        );
+        assert!(parse_err("ssh-ed25519").contains("Public key data truncated"));
+    }
+
+    #[test]
+    fn parse_truncated_blob() {
+        let mut truncated = blob(PUBLIC_KEY_RSA);
+        truncated.truncate(100);
+
+        assert!(
+            parse_err(&format!("ssh-rsa {}", base64::encode(&truncated)))
+                .contains("Public key data truncated")
+        );
+        assert!(from_blob(&truncated).is_err());
+    }
+
+    #[test]
+    fn parse_trailing_data() {
+        let mut trailing = blob(PUBLIC_KEY_ED25519);
+        trailing.push(0);
+
+        assert!(
+            parse_err(&format!("ssh-ed25519 {}", base64::encode(&trailing)))
+                .contains("Unexpected trailing public key data")
+        );
+    }
+
+    #[test]
+    fn parse_algorithm_mismatch() {
+        assert_eq!(
+            parse_err(&PUBLIC_KEY_ED25519.replace("ssh-ed25519 ", "ssh-rsa ")),
+            "Public key data is of type ssh-ed25519 but prefixed with ssh-rsa"
+        );
+        assert_eq!(
+            parse_err(&PUBLIC_KEY_ECDSA.replace("ecdsa-sha2-nistp384 ", "ecdsa-sha2-nistp256 ")),
+            "Public key data is of type ecdsa-sha2-nistp384 but prefixed with ecdsa-sha2-nistp256"
+        );
+        assert_eq!(
+            parse_err(&PUBLIC_KEY_ED25519.replace("ssh-ed25519 ", "ssh-foo ")),
+            "Unsupported public key algorithm \"ssh-foo\""
+        );
+    }
+
+    #[test]
+    fn matches_fingerprints() {
+        let key = parse(PUBLIC_KEY_ED25519).unwrap();
+
+        assert!(key.matches_fingerprint("SHA256:a5lcfVLkzdMXMaOPcon5tzlRKbyidKwb6TIZpBUcidM"));
+        assert!(key.matches_fingerprint("a5lcfVLkzdMXMaOPcon5tzlRKbyidKwb6TIZpBUcidM"));
+        assert!(key.matches_fingerprint("MD5:2B:FA:67:C8:0F:86:B9:0F:87:9E:7E:FC:61:3D:B9:C0"));
+        assert!(key.matches_fingerprint("2b:fa:67:c8:0f:86:b9:0f:87:9e:7e:fc:61:3d:b9:c0"));
+        assert!(!key.matches_fingerprint("SHA256:dbFQJWxF54jHnI+BBogvpK8ZkFmx4hDnzfYPdKchMO0"));
+        // sha256 is case sensitive
+        assert!(!key.matches_fingerprint("sha256:a5lcfvlkzdmxmaopcon5tzlrkbyidkwb6tizpbucidm"));
+    }
+}
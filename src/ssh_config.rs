@@ -1,16 +1,24 @@
-use std::collections::HashMap;
 use std::env;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::BufRead;
 use std::io::BufReader;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug)]
+// same limit as OpenSSH
+const INCLUDE_DEPTH_MAX: usize = 16;
+
+#[derive(Debug, Clone)]
 pub struct SSHConfigHost {
     pub hostname: String,
     pub user: String,
     pub port: String,
+    pub user_known_hosts_file: Option<String>,
+    pub identity_files: Vec<String>,
+    pub identities_only: bool,
+    pub proxy_jump: Option<String>,
+    // a Host or Match block matched, not only options outside of any block
+    pub matched: bool,
 }
 
 impl Default for SSHConfigHost {
@@ -19,80 +27,569 @@ impl Default for SSHConfigHost {
             hostname: String::new(),
             user: String::new(),
             port: "22".to_owned(),
+            user_known_hosts_file: None,
+            identity_files: vec![],
+            identities_only: false,
+            proxy_jump: None,
+            matched: false,
         }
     }
 }
 
-pub fn get() -> Result<HashMap<String, SSHConfigHost>, Box<Error>> {
-    let mut ssh_config = HashMap::new();
+#[derive(Debug, Clone)]
+enum Criteria {
+    // options before the first Host or Match line
+    All,
+    Host(Vec<String>),
+    // (negated, criterion, argument), e.g. Match !host *.prod
+    Match(Vec<(bool, String, Option<String>)>),
+}
 
-    // guess ~/.ssh/config path
-    let ssh_config_path = match env::home_dir() {
-        Some(path) => path.join(".ssh").join("config"),
-        None => Path::new("").to_path_buf(),
+#[derive(Debug)]
+struct Block {
+    criteria: Criteria,
+    // lowercase keyword and arguments
+    options: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Debug)]
+pub struct SSHConfig {
+    blocks: Vec<Block>,
+}
+
+impl Default for SSHConfig {
+    fn default() -> SSHConfig {
+        SSHConfig { blocks: vec![] }
+    }
+}
+
+// glob like pattern with * and ?, case insensitive like host names
+fn match_pattern(s: &str, pattern: &str) -> bool {
+    let s: Vec<char> = s.to_lowercase().chars().collect();
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+
+    // position of the last * and the char of s it matched up to
+    let (mut si, mut pi) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while si < s.len() {
+        if pi < pattern.len() && (pattern[pi] == '?' || pattern[pi] == s[si]) {
+            si += 1;
+            pi += 1;
+        } else if pi < pattern.len() && pattern[pi] == '*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if let Some((star_pi, star_si)) = star {
+            pi = star_pi + 1;
+            si = star_si + 1;
+            star = Some((star_pi, star_si + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[pi..].iter().all(|c| *c == '*')
+}
+
+// negated patterns and patterns with * or ? match more than one host
+fn is_wildcard(pattern: &str) -> bool {
+    pattern.starts_with('!') || pattern.contains('*') || pattern.contains('?')
+}
+
+// e.g. *.prod,!db.prod - a matching negated pattern never matches
+fn match_pattern_list<'a, I: Iterator<Item = &'a str>>(s: &str, patterns: I) -> bool {
+    let mut matched = false;
+
+    for pattern in patterns {
+        if pattern.starts_with('!') {
+            if match_pattern(s, &pattern[1..]) {
+                return false;
+            }
+        } else if match_pattern(s, pattern) {
+            matched = true;
+        }
+    }
+
+    matched
+}
+
+// splits into arguments, double quotes group whitespace
+fn args_split(s: &str) -> Result<Vec<String>, Box<Error>> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut in_quotes = false;
+
+    for c in s.chars() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+            in_arg = true;
+        } else if c.is_whitespace() && !in_quotes {
+            if in_arg {
+                args.push(arg);
+                arg = String::new();
+                in_arg = false;
+            }
+        } else {
+            arg.push(c);
+            in_arg = true;
+        }
+    }
+
+    if in_quotes {
+        return Err(From::from(format!("Unbalanced quotes in \"{}\"", s)));
+    }
+
+    if in_arg {
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+// keyword and arguments of a line, e.g. "Port 22", "Port=22", "Port\t= 22"
+fn line_split(line: &str) -> Result<Option<(String, Vec<String>)>, Box<Error>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let keyword_end = line.find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let keyword = line[..keyword_end].to_lowercase();
+
+    let mut rest = line[keyword_end..].trim_left();
+    if rest.starts_with('=') {
+        rest = rest[1..].trim_left();
+    }
+
+    Ok(Some((keyword, args_split(rest)?)))
+}
+
+fn match_criteria_parse(args: &[String]) -> Result<Criteria, Box<Error>> {
+    let mut criteria = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let (negated, criterion) = if arg.starts_with('!') {
+            (true, arg[1..].to_lowercase())
+        } else {
+            (false, arg.to_lowercase())
+        };
+
+        let argument = match criterion.as_str() {
+            "all" | "canonical" | "final" => None,
+            _ => match args.next() {
+                Some(a) => Some(a.to_owned()),
+                None => {
+                    return Err(From::from(format!(
+                        "Match {} requires an argument",
+                        criterion
+                    )))
+                }
+            },
+        };
+
+        criteria.push((negated, criterion, argument));
+    }
+
+    Ok(Criteria::Match(criteria))
+}
+
+fn path_expand(path: &str, base_dir: &Path) -> PathBuf {
+    if path.starts_with("~/") {
+        return env::home_dir()
+            .unwrap_or(PathBuf::new())
+            .join(&path[2..]);
+    }
+
+    // relative includes of a user config are relative to ~/.ssh
+    base_dir.join(path)
+}
+
+// files matching a path with wildcards in the file name, sorted like glob(3)
+fn include_files(path: &Path) -> Vec<PathBuf> {
+    let file_name = match path.file_name().and_then(|f| f.to_str()) {
+        Some(f) => f.to_owned(),
+        None => return vec![],
     };
 
-    if !ssh_config_path.exists() {
-        return Ok(ssh_config);
+    if !file_name.contains('*') && !file_name.contains('?') {
+        return vec![path.to_path_buf()];
     }
 
-    // open ssh config
-    let ssh_config_file = match File::open(&ssh_config_path) {
-        Ok(f) => f,
-        Err(e) => {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut files: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|f| f.to_str())
+                    .map(|f| !f.starts_with('.') && match_pattern(f, &file_name))
+                    .unwrap_or(false)
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+
+    files.sort();
+    files
+}
+
+impl SSHConfig {
+    // relative Include paths are resolved against include_base_dir
+    pub fn from_file(path: &Path, include_base_dir: &Path) -> Result<SSHConfig, Box<Error>> {
+        let mut ssh_config = SSHConfig {
+            blocks: vec![Block {
+                criteria: Criteria::All,
+                options: vec![],
+            }],
+        };
+
+        ssh_config.parse_file(path, include_base_dir, 0)?;
+        Ok(ssh_config)
+    }
+
+    fn parse_file(&mut self, path: &Path, include_base_dir: &Path, depth: usize) -> Result<(), Box<Error>> {
+        if depth > INCLUDE_DEPTH_MAX {
             return Err(From::from(format!(
-                "SSH config file {} exists but can't be read - {}",
-                ssh_config_path.to_str().unwrap(),
-                &e.to_string()
+                "Include nested too deeply at {}",
+                path.display()
             )));
         }
-    };
 
-    // parse file
-    let ssh_config_reader = BufReader::new(&ssh_config_file);
-    let mut host = String::new();
-    for (_, line) in ssh_config_reader.lines().enumerate() {
-        let mut line = line.unwrap();
-
-        // Host
-        if line.starts_with("Host") {
-            host = line.split(" ").collect::<Vec<&str>>()[1].to_owned();
-            ssh_config.insert(
-                host.to_owned(),
-                SSHConfigHost {
-                    ..Default::default()
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(From::from(format!(
+                    "SSH config file {} exists but can't be read - {}",
+                    path.display(),
+                    &e.to_string()
+                )));
+            }
+        };
+
+        for (line_no, line) in BufReader::new(&file).lines().enumerate() {
+            let line = line?;
+
+            let (keyword, args) = match line_split(&line) {
+                Ok(Some(l)) => l,
+                Ok(None) => continue,
+                Err(e) => {
+                    return Err(From::from(format!(
+                        "{} line {}: {}",
+                        path.display(),
+                        line_no + 1,
+                        e
+                    )))
+                }
+            };
+
+            match keyword.as_str() {
+                "host" => self.blocks.push(Block {
+                    criteria: Criteria::Host(args),
+                    options: vec![],
+                }),
+                "match" => {
+                    let criteria = match match_criteria_parse(&args) {
+                        Ok(c) => c,
+                        Err(e) => {
+                            return Err(From::from(format!(
+                                "{} line {}: {}",
+                                path.display(),
+                                line_no + 1,
+                                e
+                            )))
+                        }
+                    };
+
+                    self.blocks.push(Block {
+                        criteria: criteria,
+                        options: vec![],
+                    });
+                }
+                "include" => {
+                    let criteria = self.blocks[self.blocks.len() - 1].criteria.clone();
+
+                    for arg in &args {
+                        for include_file in include_files(&path_expand(arg, include_base_dir)) {
+                            if include_file.exists() {
+                                self.parse_file(&include_file, include_base_dir, depth + 1)?;
+                            }
+                        }
+                    }
+
+                    // lines after the Include belong to the block it's part of
+                    self.blocks.push(Block {
+                        criteria: criteria,
+                        options: vec![],
+                    });
+                }
+                _ => {
+                    let block_last = self.blocks.len() - 1;
+                    self.blocks[block_last].options.push((keyword, args));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn block_matches(&self, criteria: &Criteria, name: &str, resolved: &SSHConfigHost) -> bool {
+        match *criteria {
+            Criteria::All => true,
+            Criteria::Host(ref patterns) => {
+                match_pattern_list(name, patterns.iter().map(|p| p.as_str()))
+            }
+            Criteria::Match(ref criteria) => criteria.iter().all(|&(negated, ref criterion, ref argument)| {
+                let argument = argument.as_ref().map(|a| a.as_str()).unwrap_or("");
+                let hostname = if resolved.hostname.is_empty() {
+                    name
+                } else {
+                    &resolved.hostname
+                };
+
+                let matched = match criterion.as_str() {
+                    "all" | "final" => true,
+                    "host" => match_pattern_list(hostname, argument.split(',')),
+                    "originalhost" => match_pattern_list(name, argument.split(',')),
+                    "user" => match_pattern_list(&resolved.user, argument.split(',')),
+                    "localuser" => match_pattern_list(
+                        &env::var("USER").unwrap_or(String::new()),
+                        argument.split(','),
+                    ),
+                    // exec is never run, canonical and others are not supported
+                    _ => false,
+                };
+
+                matched != negated
+            }),
+        }
+    }
+
+    // options for a host name, the first obtained value of an option wins like in ssh
+    pub fn host(&self, name: &str) -> SSHConfigHost {
+        let mut host = SSHConfigHost {
+            ..Default::default()
+        };
+
+        let mut port_set = false;
+        let mut identities_only_set = false;
+
+        for block in &self.blocks {
+            if !self.block_matches(&block.criteria, name, &host) {
+                continue;
+            }
+
+            // Host * and other wildcards apply to any host, they don't make it known to ssh_config
+            match block.criteria {
+                Criteria::All => {}
+                Criteria::Host(ref patterns) => if patterns
+                    .iter()
+                    .any(|p| !is_wildcard(p) && match_pattern(name, p))
+                {
+                    host.matched = true;
                 },
-            );
+                Criteria::Match(_) => host.matched = true,
+            }
+
+            for &(ref keyword, ref args) in &block.options {
+                let value = match args.first() {
+                    Some(v) => v.to_owned(),
+                    None => continue,
+                };
+
+                match keyword.as_str() {
+                    "hostname" => if host.hostname.is_empty() {
+                        host.hostname = value.replace("%h", name).replace("%%", "%");
+                    },
+                    "user" => if host.user.is_empty() {
+                        host.user = value;
+                    },
+                    "port" => if !port_set && value.parse::<u16>().is_ok() {
+                        host.port = value;
+                        port_set = true;
+                    },
+                    "userknownhostsfile" => if host.user_known_hosts_file.is_none() {
+                        host.user_known_hosts_file = Some(args.join(" "));
+                    },
+                    // all IdentityFile lines are used, in order
+                    "identityfile" => if !host.identity_files.contains(&value) {
+                        host.identity_files.push(value);
+                    },
+                    "identitiesonly" => if !identities_only_set {
+                        host.identities_only = value.to_lowercase() == "yes";
+                        identities_only_set = true;
+                    },
+                    "proxyjump" => if host.proxy_jump.is_none() {
+                        host.proxy_jump = Some(value);
+                    },
+                    _ => {}
+                }
+            }
         }
-        // config option
-        else if !host.is_empty()
-            && !line.trim().is_empty()
-            && [' ', '\t'].contains(&line.chars().next().unwrap())
-        {
-            line = line.trim().to_owned();
-            let option_value = line.split(" ").collect::<Vec<&str>>();
-            let option = option_value[0].trim().to_lowercase();
-            let value = option_value[1].trim().to_owned();
-            let host_entry = ssh_config.get_mut(&host).unwrap();
-
-            match option.as_str() {
-                "hostname" => host_entry.hostname = value,
-                "user" => host_entry.user = value,
-                "port" => {
-                    // port valid?
-                    host_entry.port = match value.parse::<i32>() {
-                        Ok(i) => i.to_string(),
-                        Err(_e) => {
-                            host_entry.port.to_owned() // keep default
-                        }
+
+        if host.hostname.is_empty() {
+            host.hostname = name.to_owned();
+        }
+
+        host
+    }
+
+    // first Host pattern without wildcards whose HostName is hostname
+    pub fn host_label_by_hostname(&self, hostname: &str) -> Option<String> {
+        for block in &self.blocks {
+            if let Criteria::Host(ref patterns) = block.criteria {
+                let label = patterns.iter().find(|p| !is_wildcard(p));
+
+                if let Some(label) = label {
+                    if self.host(label).hostname == hostname {
+                        return Some(label.to_owned());
                     }
                 }
-                _ => {}
             }
-        } else {
-            host = "".to_owned();
         }
+
+        None
     }
+}
 
-    Ok(ssh_config)
+pub fn get() -> Result<SSHConfig, Box<Error>> {
+    // guess ~/.ssh/config path
+    let ssh_dir = match env::home_dir() {
+        Some(path) => path.join(".ssh"),
+        None => Path::new("").to_path_buf(),
+    };
+    let ssh_config_path = ssh_dir.join("config");
+
+    if !ssh_config_path.exists() {
+        return Ok(SSHConfig::default());
+    }
+
+    SSHConfig::from_file(&ssh_config_path, &ssh_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> SSHConfig {
+        let fixtures_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("ssh_config");
+
+        SSHConfig::from_file(&fixtures_dir.join(name), &fixtures_dir).unwrap()
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(match_pattern("web1.prod", "*.prod"));
+        assert!(match_pattern("web1.prod", "web?.prod"));
+        assert!(match_pattern("WEB1.prod", "web1.PROD"));
+        assert!(!match_pattern("web1.prod", "*.dev"));
+        assert!(!match_pattern("web10.prod", "web?.prod"));
+        assert!(match_pattern_list("web1.prod", "*.prod,!db*.prod".split(',')));
+        assert!(!match_pattern_list("db1.prod", "*.prod,!db*.prod".split(',')));
+    }
+
+    #[test]
+    fn multiple_patterns() {
+        let ssh_config = fixture("config");
+
+        assert_eq!(ssh_config.host("alpha").user, "multi");
+        assert_eq!(ssh_config.host("beta").user, "multi");
+        assert!(ssh_config.host("beta").matched);
+    }
+
+    #[test]
+    fn wildcards_first_match_wins() {
+        let ssh_config = fixture("config");
+
+        let web = ssh_config.host("web1.prod");
+        assert_eq!(web.user, "deploy");
+        assert_eq!(web.port, "2222");
+
+        // negated pattern, falls through to Host *
+        let db = ssh_config.host("db1.prod");
+        assert_eq!(db.user, "fallback");
+        assert_eq!(db.port, "22");
+
+        // defaults outside of any block
+        let unknown = ssh_config.host("unknown.example.com");
+        assert_eq!(unknown.hostname, "unknown.example.com");
+        assert_eq!(unknown.user, "fallback");
+        assert_eq!(unknown.user_known_hosts_file, Some("~/.ssh/known_hosts_test".to_owned()));
+        assert!(!unknown.matched);
+
+        // wildcards only
+        assert!(!ssh_config.host("web2.prod").matched);
+        assert!(ssh_config.host("web1.prod").matched);
+    }
+
+    #[test]
+    fn key_value_and_tabs() {
+        let ssh_config = fixture("config");
+
+        let equals = ssh_config.host("equals");
+        assert_eq!(equals.hostname, "equals.example.com");
+        assert_eq!(equals.user, "eq");
+        assert_eq!(equals.port, "2200");
+
+        let tabs = ssh_config.host("tabs");
+        assert_eq!(tabs.hostname, "tabs.example.com");
+        assert_eq!(tabs.user, "tab");
+    }
+
+    #[test]
+    fn hostname_token() {
+        assert_eq!(fixture("config").host("bastion").hostname, "bastion.example.com");
+    }
+
+    #[test]
+    fn identities_and_proxy_jump() {
+        let jumped = fixture("config").host("jumped");
+
+        assert_eq!(
+            jumped.identity_files,
+            vec!["~/.ssh/id_jumped".to_owned(), "~/.ssh/id_ed25519".to_owned()]
+        );
+        assert!(jumped.identities_only);
+        assert_eq!(jumped.proxy_jump, Some("bastion".to_owned()));
+    }
+
+    #[test]
+    fn include() {
+        let ssh_config = fixture("config");
+
+        // conf.d/*.conf
+        assert_eq!(ssh_config.host("included").hostname, "included.example.com");
+        assert_eq!(ssh_config.host("globbed").user, "globbed");
+
+        // options after an Include still belong to its Host block
+        let include_in_host = ssh_config.host("include-in-host");
+        assert_eq!(include_in_host.user, "from-include");
+        assert_eq!(include_in_host.port, "2022");
+    }
+
+    #[test]
+    fn match_blocks() {
+        let ssh_config = fixture("config");
+
+        assert_eq!(ssh_config.host("matched.example.com").user, "matched");
+        // Match exec is never run
+        assert_eq!(ssh_config.host("exec.example.com").user, "fallback");
+    }
+
+    #[test]
+    fn host_label_by_hostname() {
+        let ssh_config = fixture("config");
+
+        assert_eq!(
+            ssh_config.host_label_by_hostname("equals.example.com"),
+            Some("equals".to_owned())
+        );
+        assert_eq!(ssh_config.host_label_by_hostname("nowhere.example.com"), None);
+    }
 }
@@ -0,0 +1,115 @@
+use chrono::Utc;
+use cli_flow;
+use database::{account_label, Access, Database};
+
+fn heading_print(title: &str) {
+    println!("\n{}", title);
+    println!("{}", (0..title.len()).map(|_| "=").collect::<String>());
+}
+
+// hosts a user can log into
+pub fn user(db: &mut Database, user_id: &str) {
+    if db.user_get(user_id).is_none() {
+        cli_flow::errorln(&format!("User {} not known", user_id));
+    }
+
+    let access: Vec<Access> = db.access(&Utc::now())
+        .into_iter()
+        .filter(|a| a.user_id == user_id)
+        .collect();
+
+    heading_print(user_id);
+
+    println!("\n## Hosts");
+    for a in &access {
+        println!("* {} ({})", a.host_label(), a.path);
+    }
+
+    println!("");
+}
+
+// users able to log into a host
+pub fn host(db: &mut Database, hostname: &str) {
+    let hostname = match db.host_get(hostname) {
+        Some(h) => h.hostname.to_owned(),
+        None => {
+            cli_flow::errorln(&format!("Hostname {} not known", hostname));
+            return;
+        }
+    };
+
+    host_print(&db, &hostname, &db.access(&Utc::now()));
+    println!("");
+}
+
+fn host_print(db: &Database, hostname: &str, access: &[Access]) {
+    heading_print(hostname);
+
+    println!("\n## Users");
+    for user in &db.users {
+        for a in access
+            .iter()
+            .filter(|a| a.hostname == hostname && a.user_id == user.user_id)
+        {
+            match a.account {
+                Some(ref account) => println!("* {} as {} ({})", a.user_id, account, a.path),
+                None => println!("* {} ({})", a.user_id, a.path),
+            }
+        }
+    }
+}
+
+// all hosts, or a user x host account matrix
+pub fn list(db: &mut Database, csv: bool) {
+    let access = db.access(&Utc::now());
+
+    if csv {
+        matrix_print(&db, &access);
+        return;
+    }
+
+    for host in &db.hosts {
+        host_print(&db, &host.hostname, &access);
+    }
+
+    println!("");
+}
+
+// quotes fields with separators, quotes or line breaks, see RFC 4180
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
+        return format!("\"{}\"", field.replace("\"", "\"\""));
+    }
+
+    field.to_owned()
+}
+
+// one row per user, one column per account of a host. Cells hold the paths of the access
+fn matrix_print(db: &Database, access: &[Access]) {
+    let mut columns: Vec<String> = Vec::new();
+    for host in &db.hosts {
+        for account in db.host_sync_accounts(&host) {
+            columns.push(account_label(&host.hostname, account.as_ref().map(|a| &**a)));
+        }
+    }
+
+    let mut header = vec![String::from("user")];
+    header.extend(columns.iter().map(|c| csv_field(c)));
+    println!("{}", header.join(","));
+
+    for user in &db.users {
+        let mut row = vec![csv_field(&user.user_id)];
+
+        for column in &columns {
+            let paths: Vec<&str> = access
+                .iter()
+                .filter(|a| a.user_id == user.user_id && &a.host_label() == column)
+                .map(|a| &*a.path)
+                .collect();
+
+            row.push(csv_field(&paths.join("; ")));
+        }
+
+        println!("{}", row.join(","));
+    }
+}
@@ -0,0 +1,30 @@
+use cli_flow;
+use database::Database;
+use public_key;
+
+pub fn keys(db: &mut Database) {
+    let mut violations = 0;
+
+    for user in &db.users {
+        for user_public_key in &user.public_keys {
+            let violation = match public_key::parse(&user_public_key.key) {
+                Ok(k) => db.key_policy.check(&k).err(),
+                Err(e) => Some(format!("Invalid public key - {}", e.to_string())),
+            };
+
+            if let Some(violation) = violation {
+                println!(
+                    "* {} ({}): {}",
+                    user.user_id, user_public_key.label, violation
+                );
+                violations += 1;
+            }
+        }
+    }
+
+    if violations > 0 {
+        cli_flow::errorln(&format!("{} key policy violation(s) found", violations));
+    }
+
+    cli_flow::okln("All public keys comply with the key policy");
+}
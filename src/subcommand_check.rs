@@ -0,0 +1,125 @@
+use authorized_keys;
+use chrono::Utc;
+use cli_flow;
+use connection::{self, ConnectOptions};
+use database::{account_label, Database};
+use ssh_config::{self, SSHConfig};
+use subcommand_sync;
+
+pub fn check(db: &Database, options: &ConnectOptions) {
+    if options.non_interactive && options.password_auth {
+        cli_flow::errorln("Password authentication is not supported in non-interactive mode");
+    }
+
+    let ssh_config = match ssh_config::get() {
+        Ok(c) => c,
+        Err(e) => {
+            cli_flow::warningln(&e.to_string());
+            SSHConfig::default()
+        }
+    };
+
+    // read-only, hosts with lapsed grants count as pending sync without being marked
+    let now = Utc::now();
+
+    let mut hosts_drifted: Vec<String> = Vec::new();
+    let mut hosts_failed: Vec<String> = Vec::new();
+
+    for host in &db.hosts {
+        for account in db.host_sync_accounts(&host) {
+            let account = account.as_ref().map(|a| &**a);
+            let host_label = account_label(&host.hostname, account);
+
+            println!("");
+            cli_flow::infoln(&format!("# Checking host {}...", host_label));
+            println!("");
+
+            let conn = match connection::connect(&host, &ssh_config, &options.for_account(account))
+            {
+                Ok(c) => c,
+                Err(e) => {
+                    cli_flow::warningln(&format!("{}: {}", host_label, e));
+                    hosts_failed.push(host_label);
+                    continue;
+                }
+            };
+
+            let authorized_keys_remote = match conn.authorized_keys_read() {
+                Ok(r) => r,
+                Err(e) => {
+                    cli_flow::warningln(&format!("{}: {}", host_label, e));
+                    hosts_failed.push(host_label);
+                    continue;
+                }
+            };
+
+            let authorized_keys_expected =
+                authorized_keys::render(&db, &host, account, &now);
+
+            let (authorized_keys_remote, _) = match authorized_keys::merge(
+                &host,
+                &authorized_keys_remote,
+                &authorized_keys_expected,
+            ) {
+                Ok(r) => r,
+                Err(e) => {
+                    cli_flow::warningln(&format!(
+                        "{}: {} - {}",
+                        host_label, conn.remote_authorized_keys_file, e
+                    ));
+                    hosts_failed.push(host_label);
+                    continue;
+                }
+            };
+
+            if authorized_keys_remote == authorized_keys_expected {
+                cli_flow::okln(&format!(
+                    "{} matches the database",
+                    conn.remote_authorized_keys_file
+                ));
+                continue;
+            }
+
+            subcommand_sync::diff_print(&authorized_keys_remote, &authorized_keys_expected);
+
+            // differences of hosts with pending changes are expected
+            if db.host_sync_pending(&host, &now) {
+                cli_flow::infoln(&format!(
+                    "{} differs from the database, sync pending\n",
+                    conn.remote_authorized_keys_file
+                ));
+                continue;
+            }
+
+            cli_flow::warningln(&format!(
+                "{} differs from the database\n",
+                conn.remote_authorized_keys_file
+            ));
+            hosts_drifted.push(host_label);
+        }
+    }
+
+    println!("");
+
+    if !hosts_failed.is_empty() {
+        cli_flow::warningln(&format!(
+            "Unable to check {} host(s): {}",
+            hosts_failed.len(),
+            hosts_failed.join(", ")
+        ));
+    }
+
+    if !hosts_drifted.is_empty() {
+        cli_flow::errorln(&format!(
+            "Drift detected on {} host(s): {}",
+            hosts_drifted.len(),
+            hosts_drifted.join(", ")
+        ));
+    }
+
+    if !hosts_failed.is_empty() {
+        cli_flow::errorln("Check incomplete");
+    }
+
+    cli_flow::okln("All hosts match the database");
+}
@@ -0,0 +1,90 @@
+use chrono::Utc;
+use cli_flow;
+use database::Database;
+
+pub fn list(db: &mut Database) {
+    let now = Utc::now();
+    let mut expired_sth = false;
+
+    for host in &db.hosts {
+        let expired_users: Vec<_> = host.authorized_users
+            .iter()
+            .filter(|g| g.is_expired(&now))
+            .collect();
+        let expired_groups: Vec<_> = host.authorized_user_groups
+            .iter()
+            .filter(|g| g.is_expired(&now))
+            .collect();
+
+        if expired_users.is_empty() && expired_groups.is_empty() {
+            continue;
+        }
+
+        expired_sth = true;
+
+        println!("\n{}", host.hostname);
+        println!(
+            "{}",
+            (0..host.hostname.len()).map(|_| "=").collect::<String>()
+        );
+
+        if !expired_users.is_empty() {
+            println!("\n## Expired Users");
+            for user in &expired_users {
+                println!("* {}", user);
+            }
+        }
+
+        if !expired_groups.is_empty() {
+            println!("\n## Expired Groups");
+            for group in &expired_groups {
+                println!("* {}", group);
+            }
+        }
+
+        println!("");
+    }
+
+    for host_group in &db.host_groups {
+        let expired_users: Vec<_> = host_group
+            .authorized_users
+            .iter()
+            .filter(|g| g.is_expired(&now))
+            .collect();
+        let expired_groups: Vec<_> = host_group
+            .authorized_user_groups
+            .iter()
+            .filter(|g| g.is_expired(&now))
+            .collect();
+
+        if expired_users.is_empty() && expired_groups.is_empty() {
+            continue;
+        }
+
+        expired_sth = true;
+
+        let title = format!("Host group {}", host_group.group_id);
+        println!("\n{}", title);
+        println!("{}", (0..title.len()).map(|_| "=").collect::<String>());
+
+        if !expired_users.is_empty() {
+            println!("\n## Expired Users");
+            for user in &expired_users {
+                println!("* {}", user);
+            }
+        }
+
+        if !expired_groups.is_empty() {
+            println!("\n## Expired Groups");
+            for group in &expired_groups {
+                println!("* {}", group);
+            }
+        }
+
+        println!("");
+    }
+
+    if !expired_sth {
+        cli_flow::okln("No expired grants pending to be synced");
+    }
+}
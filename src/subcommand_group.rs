@@ -1,5 +1,7 @@
+use authorized_keys;
 use cli_flow;
-use database::{Database, UserGroup};
+use database::{account_label, Database, Grant, UserGroup};
+use subcommand_hostgroup;
 
 pub fn add(db: &mut Database, group_id: &str) {
     // check group is not present
@@ -11,6 +13,7 @@ pub fn add(db: &mut Database, group_id: &str) {
     let mut group_new = vec![UserGroup {
         group_id: group_id.to_owned(),
         members: vec![],
+        member_groups: vec![],
     }];
 
     db.user_groups.append(&mut group_new);
@@ -23,12 +26,25 @@ pub fn remove(db: &mut Database, group_id: &str) {
         cli_flow::errorln(&format!("Group {} not known", group_id));
     }
 
+    // hosts of the group and of the groups containing it lose members
+    db.user_group_hosts_sync_todo(group_id);
+
     // delete grouo
     db.user_groups.retain(|u| u.group_id != group_id);
 
+    // delete group from user_groups.member_groups
+    for user_group in &mut db.user_groups {
+        user_group.member_groups.retain(move |g| g != group_id);
+    }
+
     // delete user from user_groups.members
     for host in &mut db.hosts {
-        host.authorized_user_groups.retain(move |g| g != group_id);
+        host.authorized_user_groups.retain(move |g| g.id != group_id);
+    }
+    for host_group in &mut db.host_groups {
+        host_group
+            .authorized_user_groups
+            .retain(move |g| g.id != group_id);
     }
 
     cli_flow::okln(&format!("Successfully removed group {}", group_id));
@@ -55,67 +71,174 @@ pub fn list(db: &mut Database, group_filter: &str, print_raw: bool) {
         for user in &group.members {
             println!("* {}", user);
         }
+
+        if !group.member_groups.is_empty() {
+            println!("\n## Member Groups");
+            for member_group in &group.member_groups {
+                println!("* {}", member_group);
+            }
+        }
+
+        let members_inherited: Vec<_> = db.group_members(&group)
+            .into_iter()
+            .filter_map(|(user_id, via)| via.map(|g| (user_id, g)))
+            .collect();
+
+        if !members_inherited.is_empty() {
+            println!("\n## Inherited Members");
+            for (user_id, via) in members_inherited {
+                println!("* {} (via {})", user_id, via);
+            }
+        }
     }
 
     println!("");
 }
 
-pub fn grant(db: &mut Database, group_id: &str, hostname: &str) {
+pub fn grant(
+    db: &mut Database,
+    group_id: &str,
+    hostname: &str,
+    account: Option<&str>,
+    options: &[&str],
+    expires_at: Option<String>,
+) {
+    let options = match authorized_keys::options_normalize(options) {
+        Ok(o) => o,
+        Err(e) => {
+            cli_flow::errorln(&e.to_string());
+            return;
+        }
+    };
+
+    let host_label = account_label(hostname, account);
+
+    // already granted => the grant is replaced, options and expiry included
+    let grant = Grant {
+        id: String::from(group_id),
+        options: options,
+        expires_at: expires_at,
+        account: account.map(|a| a.to_owned()),
+    };
+
+    // a host or a host group
     if let Some(host) = db.host_get(hostname) {
+        if let Some(account) = account {
+            if !host.has_account(account) {
+                cli_flow::errorln(&format!(
+                    "Host {} has no account {}, add it with: host {} account add {}",
+                    hostname, account, hostname, account
+                ));
+            }
+        }
+
         if let Some(group) = db.group_get(group_id) {
-            if db.is_group_granted(&group, &host) {
+            if host.authorized_user_groups.contains(&grant) {
                 cli_flow::errorln(&format!(
                     "{} already granted to access {}",
-                    group.group_id, hostname
+                    group.group_id, host_label
+                ));
+            }
+        } else {
+            cli_flow::errorln(&format!("Group {} not known", group_id));
+        }
+    } else if let Some(host_group) = db.host_group_get(hostname) {
+        subcommand_hostgroup::account_check(&db, &host_group, account);
+
+        if let Some(group) = db.group_get(group_id) {
+            if host_group.authorized_user_groups.contains(&grant) {
+                cli_flow::errorln(&format!(
+                    "{} already granted to access host group {}",
+                    group.group_id, host_label
                 ));
             }
         } else {
             cli_flow::errorln(&format!("Group {} not known", group_id));
         }
     } else {
-        cli_flow::errorln(&format!("Hostname {} not known", hostname));
+        cli_flow::errorln(&format!("Hostname or host group {} not known", hostname));
     }
 
     // at this point it's save to mut db.host...
-    {
-        let host = db.host_get_mut(hostname).unwrap();
-        host.authorized_user_groups
-            .append(&mut vec![String::from(group_id)]);
+    if let Some(host) = db.host_get_mut(hostname) {
+        Grant::set(&mut host.authorized_user_groups, grant);
         host.sync_todo = true;
+
+        cli_flow::okln(&format!(
+            "Successfully granted group {} for host {}",
+            group_id, host_label
+        ));
+        return;
+    }
+
+    // ... or db.host_group
+    {
+        let host_group = db.host_group_get_mut(hostname).unwrap();
+        Grant::set(&mut host_group.authorized_user_groups, grant);
     }
+    db.host_group_hosts_sync_todo(hostname);
 
     cli_flow::okln(&format!(
-        "Successfully granted group {} for host {}",
-        group_id, hostname
+        "Successfully granted group {} for host group {}",
+        group_id, host_label
     ));
 }
 
-pub fn revoke(db: &mut Database, group_id: &str, hostname: &str) {
+pub fn revoke(db: &mut Database, group_id: &str, hostname: &str, account: Option<&str>) {
+    let host_label = account_label(hostname, account);
+
+    // a host or a host group
     if let Some(host) = db.host_get(hostname) {
         if let Some(group) = db.group_get(group_id) {
-            if !db.is_group_granted(&group, &host) {
+            if !db.is_group_granted(&group, &host, account) {
                 cli_flow::errorln(&format!(
                     "{} is not granted to access {}",
-                    group.group_id, hostname
+                    group.group_id, host_label
+                ));
+            }
+        } else {
+            cli_flow::errorln(&format!("Group {} not known", group_id));
+        }
+    } else if let Some(host_group) = db.host_group_get(hostname) {
+        if let Some(group) = db.group_get(group_id) {
+            if !db.is_group_granted_host_group(&group, &host_group, account) {
+                cli_flow::errorln(&format!(
+                    "{} is not granted to access host group {}",
+                    group.group_id, host_label
                 ));
             }
         } else {
             cli_flow::errorln(&format!("Group {} not known", group_id));
         }
     } else {
-        cli_flow::errorln(&format!("Hostname {} not known", hostname));
+        cli_flow::errorln(&format!("Hostname or host group {} not known", hostname));
     }
 
     // at this point it's save to mut db.host...
-    {
-        let host = db.host_get_mut(hostname).unwrap();
-        host.authorized_user_groups.retain(|u| u != group_id);
+    if let Some(host) = db.host_get_mut(hostname) {
+        host.authorized_user_groups
+            .retain(|g| g.id != group_id || !g.is_account(account));
         host.sync_todo = true;
+
+        cli_flow::okln(&format!(
+            "Successfully revoked group {} from host {}",
+            group_id, host_label
+        ));
+        return;
+    }
+
+    // ... or db.host_group
+    db.host_group_hosts_sync_todo(hostname);
+    {
+        let host_group = db.host_group_get_mut(hostname).unwrap();
+        host_group
+            .authorized_user_groups
+            .retain(|g| g.id != group_id || !g.is_account(account));
     }
 
     cli_flow::okln(&format!(
-        "Successfully revoked group {} from host {}",
-        group_id, hostname
+        "Successfully revoked group {} from host group {}",
+        group_id, host_label
     ));
 }
 
@@ -123,7 +246,8 @@ pub fn user_add(db: &mut Database, group_id: &str, user_id: &str) {
     // check user and group exist
     if let Some(user) = db.user_get(user_id) {
         if let Some(group) = db.group_get(group_id) {
-            if db.is_user_group_member(&user, &group) {
+            // members of nested groups may be added directly, they stay when leaving the nested one
+            if group.members.contains(&user.user_id) {
                 cli_flow::errorln(&format!(
                     "User {} is already member of group {}",
                     user_id, group_id
@@ -141,16 +265,8 @@ pub fn user_add(db: &mut Database, group_id: &str, user_id: &str) {
         let group = db.group_get_mut(group_id).unwrap();
         group.members.append(&mut vec![String::from(user_id)]);
     }
-    {
-        // set sync todo for affected hosts
-        for host in &mut db.hosts {
-            for authorized_group in &mut host.authorized_user_groups {
-                if authorized_group == group_id {
-                    host.sync_todo = true;
-                }
-            }
-        }
-    }
+    // set sync todo for affected hosts, directly or through host groups
+    db.user_group_hosts_sync_todo(group_id);
 
     cli_flow::okln(&format!(
         "Successfully added user {} to group {}",
@@ -168,6 +284,19 @@ pub fn user_remove(db: &mut Database, group_id: &str, user_id: &str) {
                     user_id, group_id
                 ));
             }
+
+            if !group.members.contains(&user.user_id) {
+                let via = db.group_members(&group)
+                    .into_iter()
+                    .find(|&(u, _)| u == user_id)
+                    .and_then(|(_, via)| via.map(|g| g.group_id.to_owned()))
+                    .unwrap_or(String::new());
+
+                cli_flow::errorln(&format!(
+                    "User {} is a member of group {} through group {}, remove it there",
+                    user_id, group_id, via
+                ));
+            }
         } else {
             cli_flow::errorln(&format!("Group {} not known", group_id));
         }
@@ -180,19 +309,84 @@ pub fn user_remove(db: &mut Database, group_id: &str, user_id: &str) {
         let group = db.group_get_mut(group_id).unwrap();
         group.members.retain(|u| u != user_id);
     }
-    {
-        // set sync todo for affected hosts
-        for host in &mut db.hosts {
-            for authorized_group in &mut host.authorized_user_groups {
-                if authorized_group == group_id {
-                    host.sync_todo = true;
-                }
+    // set sync todo for affected hosts, directly or through host groups
+    db.user_group_hosts_sync_todo(group_id);
+
+    cli_flow::okln(&format!(
+        "Successfully removed user {} from group {}",
+        group_id, group_id
+    ));
+}
+
+pub fn group_add(db: &mut Database, group_id: &str, member_group_id: &str) {
+    // check both groups exist
+    if let Some(group) = db.group_get(group_id) {
+        if let Some(member_group) = db.group_get(member_group_id) {
+            if group.member_groups.iter().any(|g| g == member_group_id) {
+                cli_flow::errorln(&format!(
+                    "Group {} is already member of group {}",
+                    member_group_id, group_id
+                ));
+            }
+
+            // group_id within the nested groups of member_group_id => cycle
+            if db.group_descendants(&member_group)
+                .iter()
+                .any(|g| g.group_id == group_id)
+            {
+                cli_flow::errorln(&format!(
+                    "Group {} contains group {}, nesting it would create a cycle",
+                    member_group_id, group_id
+                ));
             }
+        } else {
+            cli_flow::errorln(&format!("Group {} not known", member_group_id));
         }
+    } else {
+        cli_flow::errorln(&format!("Group {} not known", group_id));
+    }
+
+    // at this point it's save to mut db.user_groups...
+    {
+        let group = db.group_get_mut(group_id).unwrap();
+        group
+            .member_groups
+            .append(&mut vec![String::from(member_group_id)]);
     }
 
+    // set sync todo for affected hosts
+    db.user_group_hosts_sync_todo(group_id);
+
     cli_flow::okln(&format!(
-        "Successfully removed user {} from group {}",
-        group_id, group_id
+        "Successfully added group {} to group {}",
+        member_group_id, group_id
+    ));
+}
+
+pub fn group_remove(db: &mut Database, group_id: &str, member_group_id: &str) {
+    // check group exist and contains the member group
+    if let Some(group) = db.group_get(group_id) {
+        if !group.member_groups.iter().any(|g| g == member_group_id) {
+            cli_flow::errorln(&format!(
+                "Group {} is not a member of group {}",
+                member_group_id, group_id
+            ));
+        }
+    } else {
+        cli_flow::errorln(&format!("Group {} not known", group_id));
+    }
+
+    // at this point it's save to mut db.user_groups...
+    {
+        let group = db.group_get_mut(group_id).unwrap();
+        group.member_groups.retain(|g| g != member_group_id);
+    }
+
+    // set sync todo for affected hosts
+    db.user_group_hosts_sync_todo(group_id);
+
+    cli_flow::okln(&format!(
+        "Successfully removed group {} from group {}",
+        member_group_id, group_id
     ));
 }
@@ -1,5 +1,10 @@
+use backup::{self, BackupOptions};
 use cli_flow;
-use database::{Database, Host};
+use connection::{self, ConnectOptions, KnownHostsState};
+use chrono::Utc;
+use database::{account_label, Database, Host, HostKey};
+use ssh_config::{self, SSHConfig};
+use subcommand_sync;
 
 pub fn add(db: &mut Database, hostname: &str) {
     if db.host_get(hostname).is_some() {
@@ -9,6 +14,10 @@ pub fn add(db: &mut Database, hostname: &str) {
         ));
     }
 
+    if db.host_group_get(hostname).is_some() {
+        cli_flow::errorln(&format!("There is already a host group {}", hostname));
+    }
+
     // <= 1 char ':' allowed
     if hostname.matches(":").count() > 1 {
         cli_flow::errorln("Hostname format invalid. More than than one ':' found");
@@ -38,6 +47,11 @@ pub fn remove(db: &mut Database, hostname: &str) {
     }
 
     db.hosts.retain(|h| h.hostname != hostname);
+
+    // delete host from host_groups.hosts
+    for host_group in &mut db.host_groups {
+        host_group.hosts.retain(|h| h != hostname);
+    }
     cli_flow::okln(&format!("Successfully removed host {}", hostname));
 }
 
@@ -60,14 +74,51 @@ pub fn list(db: &mut Database, hostname_filter: &str, print_raw: bool) {
             (0..host.hostname.len()).map(|_| "=").collect::<String>()
         );
 
+        if host.managed_block {
+            println!("\nauthorized_keys: managed block only");
+        }
+
+        if !host.accounts.is_empty() {
+            println!("\n## Accounts");
+            for account in &host.accounts {
+                println!("* {}", account);
+            }
+        }
+
+        if let Some(ref proxy_jump) = host.proxy_jump {
+            println!("\nProxyJump: {}", proxy_jump);
+        }
+
+        if !host.host_keys.is_empty() {
+            println!("\n## Pinned Host Keys");
+            for host_key in &host.host_keys {
+                println!("* {}", host_key);
+            }
+        }
+
+        let host_groups = db.host_groups_of(&host);
+        if !host_groups.is_empty() {
+            println!("\n## Host Groups");
+            for host_group in host_groups {
+                println!("* {}", host_group);
+            }
+        }
+
+        // effective grants, the ones of host groups with the host group
         println!("\n## Authorized Users");
-        for user in &host.authorized_users {
-            println!("* {}", user);
+        for (user, host_group) in db.host_user_grants(&host) {
+            match host_group {
+                Some(g) => println!("* {} (via host group {})", user, g),
+                None => println!("* {}", user),
+            }
         }
 
         println!("\n## Authorized Groups");
-        for group in &host.authorized_user_groups {
-            println!("* {}", group);
+        for (group, host_group) in db.host_user_group_grants(&host) {
+            match host_group {
+                Some(g) => println!("* {} (via host group {})", group, g),
+                None => println!("* {}", group),
+            }
         }
 
         println!("");
@@ -102,6 +153,10 @@ pub fn alias(db: &mut Database, hostname: &str, alias_opt: Option<&str>) {
             if db.host_get_by_alias(alias).is_some() {
                 cli_flow::errorln(&format!("Host alias {} already exists", alias));
             }
+
+            if db.host_group_get(alias).is_some() {
+                cli_flow::errorln(&format!("There is already a host group {}", alias));
+            }
             {
                 let host = db.host_get_mut(hostname).unwrap();
                 host.alias = Some(alias.to_owned());
@@ -122,3 +177,412 @@ pub fn alias(db: &mut Database, hostname: &str, alias_opt: Option<&str>) {
         }
     }
 }
+
+pub fn managed_block(db: &mut Database, hostname: &str, managed_block: bool) {
+    match db.host_get_mut(hostname) {
+        Some(host) => {
+            if host.managed_block != managed_block {
+                host.managed_block = managed_block;
+                host.sync_todo = true;
+            }
+        }
+        None => {
+            cli_flow::errorln(&format!("Hostname {} not known", hostname));
+            return;
+        }
+    }
+
+    if managed_block {
+        cli_flow::okln(&format!(
+            "Successfully enabled managed block mode for host {}",
+            hostname
+        ));
+    } else {
+        cli_flow::okln(&format!(
+            "Successfully disabled managed block mode for host {}",
+            hostname
+        ));
+    }
+}
+
+pub fn account_add(db: &mut Database, hostname: &str, account: &str) {
+    // becomes part of a remote path and of the local backup directory
+    if !account
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        || account.starts_with('-')
+        || account.starts_with('.')
+    {
+        cli_flow::errorln(&format!("Invalid account name {}", account));
+    }
+
+    let host = match db.host_get_mut(hostname) {
+        Some(h) => h,
+        None => {
+            cli_flow::errorln(&format!("Hostname {} not known", hostname));
+            return;
+        }
+    };
+
+    if host.has_account(account) {
+        cli_flow::errorln(&format!(
+            "Host {} already has an account {}",
+            hostname, account
+        ));
+    }
+
+    host.accounts.push(account.to_owned());
+    host.sync_todo = true;
+
+    cli_flow::okln(&format!(
+        "Successfully added account {} to host {}",
+        account, hostname
+    ));
+}
+
+pub fn account_remove(db: &mut Database, hostname: &str, account: &str) {
+    {
+        let host = match db.host_get(hostname) {
+            Some(h) => h,
+            None => {
+                cli_flow::errorln(&format!("Hostname {} not known", hostname));
+                return;
+            }
+        };
+
+        if !host.has_account(account) {
+            cli_flow::errorln(&format!("Host {} has no account {}", hostname, account));
+        }
+
+        // the authorized_keys of the account isn't synced anymore, grants would stay on the host
+        if host.authorized_users
+            .iter()
+            .chain(host.authorized_user_groups.iter())
+            .any(|g| g.is_account(Some(account)))
+        {
+            cli_flow::errorln(&format!(
+                "Account {} of host {} has grants, revoke them first",
+                account, hostname
+            ));
+        }
+
+        for host_group in db.host_groups_of(&host) {
+            if host_group.accounts().contains(&account) {
+                cli_flow::errorln(&format!(
+                    "Account {} of host {} has grants of host group {}, revoke them first",
+                    account, hostname, host_group.group_id
+                ));
+            }
+        }
+    }
+
+    // at this point it's save to mut db.host...
+    {
+        let host = db.host_get_mut(hostname).unwrap();
+        host.accounts.retain(|a| a != account);
+    }
+
+    cli_flow::okln(&format!(
+        "Successfully removed account {} from host {}",
+        account, hostname
+    ));
+    cli_flow::infoln(&format!(
+        "The authorized_keys of {} is no longer synced and left as it is",
+        account_label(hostname, Some(account))
+    ));
+}
+
+// jump hosts to connect through, None removes them
+pub fn proxy_jump(db: &mut Database, hostname: &str, proxy_jump_opt: Option<&str>) {
+    let host = match db.host_get_mut(hostname) {
+        Some(h) => h,
+        None => {
+            cli_flow::errorln(&format!("Hostname {} not known", hostname));
+            return;
+        }
+    };
+
+    match proxy_jump_opt {
+        Some(proxy_jump) => {
+            if proxy_jump.split(',').any(|j| j.trim().is_empty()) {
+                cli_flow::errorln(&format!("Invalid jump host chain {}", proxy_jump));
+            }
+
+            host.proxy_jump = Some(proxy_jump.to_owned());
+            cli_flow::okln(&format!(
+                "Successfully set jump host {} for host {}",
+                proxy_jump, hostname
+            ));
+        }
+        None => {
+            if host.proxy_jump.is_none() {
+                cli_flow::errorln(&format!("No jump host set for host {}", hostname));
+            }
+
+            host.proxy_jump = None;
+            cli_flow::okln(&format!(
+                "Successfully removed jump host for host {}",
+                hostname
+            ));
+        }
+    }
+}
+
+// pushes a local backup (default: the latest) back to the host
+pub fn restore(
+    db: &mut Database,
+    hostname: &str,
+    account: Option<&str>,
+    backup_id: Option<&str>,
+    connect_options: &ConnectOptions,
+    backup_options: &BackupOptions,
+    yes: bool,
+) {
+    if connect_options.non_interactive && !yes {
+        cli_flow::errorln("Non-interactive mode requires --yes to confirm changes");
+    }
+
+    let host = match db.host_get(hostname) {
+        Some(h) => h,
+        None => {
+            cli_flow::errorln(&format!("Hostname {} not known", hostname));
+            return;
+        }
+    };
+
+    if let Some(account) = account {
+        if !host.has_account(account) {
+            cli_flow::errorln(&format!("Host {} has no account {}", hostname, account));
+        }
+    }
+
+    // backups are kept per account
+    let host_label = account_label(&host.hostname, account);
+
+    let backup_ids = match backup::local_list(&backup_options, &host_label) {
+        Ok(ids) => ids,
+        Err(e) => {
+            cli_flow::errorln(&format!("Unable to list backups - {}", e));
+            return;
+        }
+    };
+
+    if backup_ids.is_empty() {
+        cli_flow::errorln(&format!(
+            "No backups of host {} found in {}",
+            host_label,
+            backup_options.dir.display()
+        ));
+    }
+
+    let backup_id = match backup_id {
+        Some(id) => {
+            if !backup_ids.iter().any(|b| b == id) {
+                cli_flow::errorln(&format!(
+                    "Backup {} of host {} not found. Available backups: {}",
+                    id,
+                    host_label,
+                    backup_ids.join(", ")
+                ));
+            }
+            id.to_owned()
+        }
+        None => backup_ids[backup_ids.len() - 1].to_owned(),
+    };
+
+    let authorized_keys_backup =
+        match backup::local_read(&backup_options, &host_label, &backup_id) {
+            Ok(b) => b,
+            Err(e) => {
+                cli_flow::errorln(&format!("Unable to read backup {} - {}", backup_id, e));
+                return;
+            }
+        };
+
+    let ssh_config = match ssh_config::get() {
+        Ok(c) => c,
+        Err(e) => {
+            cli_flow::warningln(&e.to_string());
+            SSHConfig::default()
+        }
+    };
+
+    println!("");
+    cli_flow::infoln(&format!(
+        "# Restoring backup {} to host {}...",
+        backup_id, host_label
+    ));
+    println!("");
+
+    let conn = match connection::connect(
+        &host,
+        &ssh_config,
+        &connect_options.for_account(account),
+    ) {
+        Ok(c) => c,
+        Err(e) => {
+            cli_flow::errorln(&e.to_string());
+            return;
+        }
+    };
+
+    let authorized_keys_remote_existing = match conn.authorized_keys_read_existing() {
+        Ok(r) => r,
+        Err(e) => {
+            cli_flow::errorln(&e.to_string());
+            return;
+        }
+    };
+    let authorized_keys_remote = authorized_keys_remote_existing
+        .clone()
+        .unwrap_or_default();
+
+    if authorized_keys_remote == authorized_keys_backup {
+        cli_flow::okln(&format!(
+            "{} already matches backup {}, nothing to restore",
+            conn.remote_authorized_keys_file, backup_id
+        ));
+        return;
+    }
+
+    subcommand_sync::diff_print(&authorized_keys_remote, &authorized_keys_backup);
+
+    if !yes && cli_flow::prompt_yes_no(
+        &mut format!(
+            "Verify changes. Do you want to restore {}? (y/n):",
+            conn.remote_authorized_keys_file
+        ),
+        true,
+    ) == "n"
+    {
+        cli_flow::warningln("Skipping restore as you told so");
+        return;
+    }
+
+    // the restore itself can be undone with the backup of the current content
+    if let Some(ref authorized_keys) = authorized_keys_remote_existing {
+        if let Err(e) =
+            subcommand_sync::backup_write(&host_label, &conn, &authorized_keys, &backup_options)
+        {
+            cli_flow::errorln(&e.to_string());
+            return;
+        }
+    }
+
+    if let Err(e) = conn
+        .authorized_keys_write(&authorized_keys_backup)
+        .and_then(|_| conn.authorized_keys_verify(&authorized_keys_backup))
+    {
+        cli_flow::errorln(&e.to_string());
+    }
+
+    cli_flow::okln(&format!(
+        "Successfully restored backup {} to {}",
+        backup_id, conn.remote_authorized_keys_file
+    ));
+    cli_flow::infoln(
+        "The database is unchanged, check reports the host as drifted until both match again",
+    );
+}
+
+// pins the host key presented by the host, verified on every connect instead of known_hosts
+pub fn trust(
+    db: &mut Database,
+    hostname: &str,
+    replace: bool,
+    connect_options: &ConnectOptions,
+    yes: bool,
+) {
+    if connect_options.non_interactive && !yes {
+        cli_flow::errorln("Non-interactive mode requires --yes to confirm changes");
+    }
+
+    let ssh_config = match ssh_config::get() {
+        Ok(c) => c,
+        Err(e) => {
+            cli_flow::warningln(&e.to_string());
+            SSHConfig::default()
+        }
+    };
+
+    let host = match db.host_get_mut(hostname) {
+        Some(h) => h,
+        None => {
+            cli_flow::errorln(&format!("Hostname {} not known", hostname));
+            return;
+        }
+    };
+
+    let (host_key, known_hosts_state) =
+        match connection::host_key_get(&host, &ssh_config, &connect_options) {
+            Ok(k) => k,
+            Err(e) => {
+                cli_flow::errorln(&e.to_string());
+                return;
+            }
+        };
+
+    let host_key = HostKey {
+        algorithm: host_key.algorithm.to_string(),
+        fingerprint: host_key.fingerprint_sha256(),
+        added_at: format!("{}", Utc::now()),
+    };
+
+    cli_flow::infoln(&format!("Host {} presents {}", host.hostname, host_key));
+
+    // known_hosts is a second opinion on the key presented
+    match known_hosts_state {
+        KnownHostsState::Match => cli_flow::infoln("The host key matches known_hosts"),
+        KnownHostsState::NotFound => cli_flow::warningln(
+            "The host key is not in known_hosts, compare the fingerprint with the one of the host",
+        ),
+        KnownHostsState::Mismatch => cli_flow::errorln(&format!(
+            "Host key {} of host {} does not match known_hosts, someone could be doing something nasty!",
+            host_key, host.hostname
+        )),
+        KnownHostsState::Revoked => cli_flow::errorln(&format!(
+            "Host key {} of host {} is marked as @revoked in known_hosts",
+            host_key, host.hostname
+        )),
+    }
+
+    if host.host_keys
+        .iter()
+        .any(|k| k.fingerprint == host_key.fingerprint)
+    {
+        cli_flow::okln(&format!(
+            "Host key {} of host {} is already trusted",
+            host_key, host.hostname
+        ));
+        return;
+    }
+
+    if !host.host_keys.is_empty() && !replace {
+        cli_flow::errorln(&format!(
+            "Host {} presents {} but {} is pinned. Use --replace if the host key was changed on purpose",
+            host.hostname,
+            host_key,
+            host.host_keys
+                .iter()
+                .map(|k| k.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+    }
+
+    // trust confirmation
+    if !yes && cli_flow::prompt_yes_no(
+        &format!("Trust host key {} of {}? (y/n):", host_key, host.hostname),
+        true,
+    ) == "n"
+    {
+        cli_flow::warningln("Host key not pinned as you told so");
+        return;
+    }
+
+    cli_flow::okln(&format!(
+        "Successfully pinned host key {} for host {}",
+        host_key, host.hostname
+    ));
+    host.host_keys = vec![host_key];
+}
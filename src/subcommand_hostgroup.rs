@@ -0,0 +1,186 @@
+use cli_flow;
+use database::{Database, HostGroup};
+
+pub fn add(db: &mut Database, group_id: &str) {
+    // check host group is not present
+    if db.host_group_get(group_id).is_some() {
+        cli_flow::errorln(&format!("Host group {} already exists", group_id));
+    }
+
+    // grant targets are looked up by hostname, alias or host group
+    if db.host_get(group_id).is_some() {
+        cli_flow::errorln(&format!(
+            "There is already a host with hostname or alias {}",
+            group_id
+        ));
+    }
+
+    // add new host group
+    let mut host_group_new = vec![HostGroup {
+        group_id: group_id.to_owned(),
+        hosts: vec![],
+        authorized_users: vec![],
+        authorized_user_groups: vec![],
+    }];
+
+    db.host_groups.append(&mut host_group_new);
+    cli_flow::okln(&format!("Successfully added host group {}", group_id));
+}
+
+pub fn remove(db: &mut Database, group_id: &str) {
+    // check host group exist
+    if db.host_group_get(group_id).is_none() {
+        cli_flow::errorln(&format!("Host group {} not known", group_id));
+    }
+
+    // grants of the host group are gone for all members
+    db.host_group_hosts_sync_todo(group_id);
+    db.host_groups.retain(|g| g.group_id != group_id);
+
+    cli_flow::okln(&format!("Successfully removed host group {}", group_id));
+}
+
+pub fn list(db: &mut Database, group_filter: &str, print_raw: bool) {
+    for host_group in &db.host_groups {
+        if !group_filter.is_empty() && group_filter != host_group.group_id {
+            continue;
+        }
+
+        if print_raw {
+            println!("{:?}", host_group);
+            continue;
+        }
+
+        println!("\n{}", host_group.group_id);
+        println!(
+            "{}",
+            (0..host_group.group_id.len())
+                .map(|_| "=")
+                .collect::<String>()
+        );
+
+        println!("\n## Hosts");
+        for hostname in &host_group.hosts {
+            println!("* {}", hostname);
+        }
+
+        println!("\n## Authorized Users");
+        for user in &host_group.authorized_users {
+            println!("* {}", user);
+        }
+
+        println!("\n## Authorized Groups");
+        for group in &host_group.authorized_user_groups {
+            println!("* {}", group);
+        }
+    }
+
+    println!("");
+}
+
+// grants with --as need the account on every member of the host group
+pub fn account_check(db: &Database, host_group: &HostGroup, account: Option<&str>) {
+    let account = match account {
+        Some(a) => a,
+        None => return,
+    };
+
+    for hostname in &host_group.hosts {
+        if let Some(host) = db.host_get(hostname) {
+            if !host.has_account(account) {
+                cli_flow::errorln(&format!(
+                    "Host {} of host group {} has no account {}, add it with: host {} account add {}",
+                    hostname, host_group.group_id, account, hostname, account
+                ));
+            }
+        }
+    }
+}
+
+pub fn host_add(db: &mut Database, group_id: &str, hostname: &str) {
+    // check host and host group exist
+    let hostname = match db.host_get(hostname) {
+        Some(host) => {
+            if let Some(host_group) = db.host_group_get(group_id) {
+                if host_group.hosts.contains(&host.hostname) {
+                    cli_flow::errorln(&format!(
+                        "Host {} is already member of host group {}",
+                        hostname, group_id
+                    ));
+                }
+
+                for account in host_group.accounts() {
+                    if !host.has_account(account) {
+                        cli_flow::errorln(&format!(
+                            "Host {} has no account {} used by grants of host group {}",
+                            hostname, account, group_id
+                        ));
+                    }
+                }
+            } else {
+                cli_flow::errorln(&format!("Host group {} not known", group_id));
+            }
+
+            // aliases are resolved, members are kept by hostname
+            host.hostname.to_owned()
+        }
+        None => {
+            cli_flow::errorln(&format!("Hostname {} not known", hostname));
+            return;
+        }
+    };
+
+    // at this point it's save to mut db.host_groups...
+    {
+        let host_group = db.host_group_get_mut(group_id).unwrap();
+        host_group.hosts.append(&mut vec![hostname.to_owned()]);
+    }
+    {
+        let host = db.host_get_mut(&hostname).unwrap();
+        host.sync_todo = true;
+    }
+
+    cli_flow::okln(&format!(
+        "Successfully added host {} to host group {}",
+        hostname, group_id
+    ));
+}
+
+pub fn host_remove(db: &mut Database, group_id: &str, hostname: &str) {
+    // check host and host group exist
+    let hostname = match db.host_get(hostname) {
+        Some(host) => {
+            if let Some(host_group) = db.host_group_get(group_id) {
+                if !host_group.hosts.contains(&host.hostname) {
+                    cli_flow::errorln(&format!(
+                        "Host {} is not a member of host group {}",
+                        hostname, group_id
+                    ));
+                }
+            } else {
+                cli_flow::errorln(&format!("Host group {} not known", group_id));
+            }
+
+            host.hostname.to_owned()
+        }
+        None => {
+            cli_flow::errorln(&format!("Hostname {} not known", hostname));
+            return;
+        }
+    };
+
+    // at this point it's save to mut db.host_groups...
+    {
+        let host_group = db.host_group_get_mut(group_id).unwrap();
+        host_group.hosts.retain(|h| h != &hostname);
+    }
+    {
+        let host = db.host_get_mut(&hostname).unwrap();
+        host.sync_todo = true;
+    }
+
+    cli_flow::okln(&format!(
+        "Successfully removed host {} from host group {}",
+        hostname, group_id
+    ));
+}
@@ -1,383 +1,483 @@
+use authorized_keys;
+use backup::{self, BackupOptions};
+use chrono::Utc;
 use cli_flow;
 use colored::Colorize;
-use database::Database;
+use connection::{self, Auth, ConnectOptions, Connection};
+use database::{account_label, Database, Host};
 use difference::{Changeset, Difference};
-use rpassword;
-use ssh2::Session;
-use ssh_config;
-use std::collections::HashMap;
-use std::env;
+use ssh_config::{self, SSHConfig};
 use std::error::Error;
-use std::io::prelude::*;
-use std::io::Read;
-use std::net::TcpStream;
-use std::path::Path;
-use std::str;
-
-fn userauth_agent(sess: &mut Session, ssh_user: &str) -> Result<bool, Box<Error>> {
-    let mut agent = try!(sess.agent());
-    try!(agent.connect());
-    agent.list_identities().unwrap();
-
-    for identity in agent.identities() {
-        let identity = try!(identity);
-        if agent.userauth(&ssh_user, &identity).is_ok() {
-            return Ok(true);
-        }
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Clone)]
+pub struct SyncOptions {
+    pub connect: ConnectOptions,
+    // automatic yes to sync confirmation prompts
+    pub yes: bool,
+    // only show the diff, don't upload
+    pub dry_run: bool,
+    // number of hosts synced concurrently, > 1 requires non-interactive mode
+    pub jobs: usize,
+    pub backup: BackupOptions,
+    // upload even if the key of the session is not in the new authorized_keys
+    pub allow_lockout: bool,
+}
+
+pub enum HostSyncStatus {
+    Synced,
+    Skipped(String),
+}
+
+// outcome of a host, errors as String as results are passed between threads
+pub type HostSyncResult = Result<HostSyncStatus, String>;
+
+// an account of a host with the authorized_keys it should have
+#[derive(Clone)]
+struct SyncTarget {
+    // index of the host in the database
+    host_index: usize,
+    host: Host,
+    account: Option<String>,
+    authorized_keys_sync: String,
+}
+
+impl SyncTarget {
+    fn account(&self) -> Option<&str> {
+        self.account.as_ref().map(|a| &**a)
     }
 
-    Ok(false)
+    // e.g. deploy@web1.example.com
+    fn label(&self) -> String {
+        account_label(&self.host.hostname, self.account())
+    }
 }
 
-pub fn sync(db: &mut Database, password_auth: bool, yes_authorized_keys_prompt: bool) {
-    let ssh_config = match ssh_config::get() {
-        Ok(c) => c,
-        Err(e) => {
-            cli_flow::warningln(&e.to_string());
-            HashMap::new()
+// show diff of authorized_keys of host <-> to sync
+pub fn diff_print(authorized_keys_remote: &str, authorized_keys_sync: &str) {
+    let Changeset { diffs, .. } =
+        Changeset::new(&authorized_keys_remote, &authorized_keys_sync, "\n");
+
+    cli_flow::infoln("");
+    for i in 0..diffs.len() {
+        match diffs[i] {
+            Difference::Same(ref x) => {
+                cli_flow::infoln(&format!("{}", x));
+            }
+            Difference::Add(ref x) => {
+                cli_flow::infoln(&format!("{}", format!("+{}", x).green()));
+            }
+            Difference::Rem(ref x) => {
+                cli_flow::infoln(&format!("{}", format!("-{}", x).red()));
+            }
         }
-    };
+    }
+}
 
-    let mut syned_sth = false;
+fn sync_host(target: &SyncTarget, ssh_config: &SSHConfig, options: &SyncOptions) -> HostSyncResult {
+    cli_flow::infoln("");
+    cli_flow::infoln(&format!("# Syncing host {}...", target.label()));
+    cli_flow::infoln("");
 
-    for host in &mut db.hosts {
-        // sync needed for host?
-        if !host.sync_todo {
-            continue;
+    let result = sync_host_try(&target, &ssh_config, &options);
+
+    if let Err(ref e) = result {
+        cli_flow::warningln(&format!("{}: {}\n", target.label(), e));
+    }
+
+    result.map_err(|e| e.to_string())
+}
+
+fn sync_host_try(
+    target: &SyncTarget,
+    ssh_config: &SSHConfig,
+    options: &SyncOptions,
+) -> Result<HostSyncStatus, Box<Error>> {
+    let host = &target.host;
+    let authorized_keys_sync = &target.authorized_keys_sync;
+
+    let conn = connection::connect(
+        &host,
+        &ssh_config,
+        &options.connect.for_account(target.account()),
+    )?;
+    let authorized_keys_remote_existing = conn.authorized_keys_read_existing()?;
+    let authorized_keys_remote_file = authorized_keys_remote_existing
+        .clone()
+        .unwrap_or_default();
+
+    let (authorized_keys_remote, authorized_keys_upload) =
+        authorized_keys::merge(&host, &authorized_keys_remote_file, &authorized_keys_sync)
+            .map_err(|e| format!("{} - {}", conn.remote_authorized_keys_file, e))?;
+
+    diff_print(&authorized_keys_remote, &authorized_keys_sync);
+
+    if let Err(e) = lockout_check(
+        &conn.auth,
+        conn.authorized_keys_own,
+        &conn.remote_authorized_keys_file,
+        &authorized_keys_upload,
+    ) {
+        if !options.allow_lockout {
+            return Err(From::from(format!("{}. Use --allow-lockout to sync anyway", e)));
         }
+        cli_flow::warningln(&format!("{}, syncing anyway (--allow-lockout)", e));
+    }
 
-        println!("");
-        cli_flow::infoln(&format!("# Syncing host {}...", host.hostname));
-        println!("");
+    if options.dry_run {
+        cli_flow::infoln(&format!(
+            "Dry run, {} left untouched\n",
+            conn.remote_authorized_keys_file
+        ));
+        return Ok(HostSyncStatus::Skipped("dry run".to_owned()));
+    }
 
-        syned_sth = true;
+    // sync confirmation
+    if !options.yes && cli_flow::prompt_yes_no(
+        &mut format!(
+            "Verify changes. Do you want to sync to {}? (y/n):",
+            conn.remote_authorized_keys_file
+        ),
+        true,
+    ) == "n"
+    {
+        cli_flow::warningln(&format!(
+            "Skipping sync of {} as you told so\n\n",
+            conn.ssh_host
+        ));
+        return Ok(HostSyncStatus::Skipped("declined".to_owned()));
+    }
 
-        // ssh connect to host
-        // defaults for connection
-        let mut ssh_host = &*host.hostname;
-        let mut ssh_port = "22";
+    // backup, nothing to keep if the file doesn't exist yet. A failed backup aborts the sync
+    if let Some(ref authorized_keys) = authorized_keys_remote_existing {
+        backup_write(&target.label(), &conn, &authorized_keys, &options.backup)?;
+    }
 
-        let mut ssh_user = String::new();
-        let ssh_user_default = "root";
+    // sync!
+    conn.authorized_keys_write(&authorized_keys_upload)?;
 
-        let mut ssh_config_used = false;
+    // only a verified upload counts as synced
+    conn.authorized_keys_verify(&authorized_keys_upload)?;
 
-        // is host to connect found in ssh_config?
-        for (cfg_host_label, cfg_host) in &ssh_config {
-            if cfg_host_label == ssh_host
-                || cfg_host_label == &(host.alias.clone()).unwrap_or("".to_string())
-                || cfg_host.hostname == ssh_host
-            {
-                cli_flow::infoln(&format!(
-                        "Found hostname or alias {} in ssh_config, using config parameters (hostname, user, port) for connection",
-                        ssh_host
-                    ));
+    cli_flow::okln(&format!(
+        "Successfully synced to {}\n",
+        conn.remote_authorized_keys_file
+    ));
 
-                ssh_host = &cfg_host.hostname;
-                ssh_user = cfg_host.user.to_owned();
-                ssh_port = &cfg_host.port;
-                ssh_config_used = true;
+    Ok(HostSyncStatus::Synced)
+}
 
-                break;
+// is the key used for this connection still in the authorized_keys to upload?
+fn lockout_check(
+    auth: &Auth,
+    authorized_keys_own: bool,
+    remote_authorized_keys_file: &str,
+    authorized_keys_upload: &str,
+) -> Result<(), Box<Error>> {
+    // authorized_keys of another user or password login, not affected
+    if !authorized_keys_own {
+        return Ok(());
+    }
+
+    match *auth {
+        Auth::Password => Ok(()),
+        Auth::PublicKey(ref blob) => {
+            if authorized_keys::contains_key(&authorized_keys_upload, &blob) {
+                return Ok(());
             }
+
+            Err(From::from(format!(
+                "The key used to connect is not in the new {}, you would lock yourself out",
+                remote_authorized_keys_file
+            )))
         }
+        Auth::Unknown(ref reason) => Err(From::from(format!(
+            "Unable to check if the key used to connect stays in {} - {}",
+            remote_authorized_keys_file, reason
+        ))),
+    }
+}
+
+// saves the current authorized_keys locally and on the host, both are required before overwriting.
+// Local backups are kept per host label, e.g. deploy@web1.example.com
+pub fn backup_write(
+    host_label: &str,
+    conn: &Connection,
+    authorized_keys: &str,
+    options: &BackupOptions,
+) -> Result<(), Box<Error>> {
+    let backup_id = backup::id_now();
+
+    let backup_local = backup::local_save(&options, &host_label, &backup_id, &authorized_keys)
+        .map_err(|e| format!("Unable to save local backup - {}", e))?;
+    let backup_remote = conn.authorized_keys_backup(&authorized_keys, &backup_id, options.retention)?;
+
+    cli_flow::infoln(&format!(
+        "Backup {} saved to {} and {}",
+        backup_id,
+        backup_local.display(),
+        backup_remote
+    ));
+
+    Ok(())
+}
+
+// syncs targets in options.jobs worker threads, returns the result per index of targets
+fn sync_hosts_parallel(
+    targets: Vec<(usize, SyncTarget)>,
+    ssh_config: SSHConfig,
+    options: &SyncOptions,
+) -> Vec<(usize, HostSyncResult)> {
+    let targets_count = targets.len();
+    let indices: Vec<usize> = targets.iter().map(|&(i, _)| i).collect();
+    let queue = Arc::new(Mutex::new(targets));
+    let ssh_config = Arc::new(ssh_config);
+    let (tx, rx) = mpsc::channel();
+
+    let mut workers = Vec::new();
+    for _ in 0..options.jobs {
+        let queue = Arc::clone(&queue);
+        let ssh_config = Arc::clone(&ssh_config);
+        let options = options.clone();
+        let tx = tx.clone();
+
+        workers.push(thread::spawn(move || loop {
+            let next = queue.lock().unwrap().pop();
+            let (i, target) = match next {
+                Some(t) => t,
+                None => break,
+            };
 
-        // hostname:port format?
-        if !ssh_config_used {
-            let host_splitted: Vec<&str> = host.hostname.split(':').collect();
+            // print the output of a host in one piece, not interleaved with other hosts
+            cli_flow::capture_start();
+            let result = sync_host(&target, &ssh_config, &options);
+            let output = cli_flow::capture_end();
 
-            // found one ':' in hostname
-            if host_splitted.len() == 2 {
-                if host_splitted[1].parse::<i32>().is_ok() {
-                    ssh_host = &*host_splitted[0];
-                    ssh_port = &*host_splitted[1];
-                }
+            if tx.send((i, result, output)).is_err() {
+                break;
             }
+        }));
+    }
+    drop(tx);
+
+    let mut results = Vec::new();
+    for (i, result, output) in rx.iter().take(targets_count) {
+        print!("{}", output);
+        results.push((i, result));
+    }
+
+    for worker in workers {
+        if worker.join().is_err() {
+            cli_flow::warningln("A sync worker thread panicked");
+        }
+    }
+
+    // a panicked worker sends no result, its target failed
+    for i in indices {
+        if !results.iter().any(|&(r, _)| r == i) {
+            results.push((i, Err("No result, the sync worker thread panicked".to_owned())));
         }
+    }
+
+    results
+}
 
-        // connect!
-        let ssh_tcp = match TcpStream::connect(&format!("{}:{}", ssh_host, ssh_port)) {
-            Ok(t) => t,
-            Err(e) => {
-                cli_flow::errorln(&e.to_string());
-                continue;
+fn summary_print(targets: &[SyncTarget], results: &[(usize, HostSyncResult)]) {
+    let hostname_width = results
+        .iter()
+        .map(|&(i, _)| targets[i].label().len())
+        .max()
+        .unwrap_or(0)
+        .max("Host".len());
+
+    let (mut synced, mut skipped, mut failed) = (0, 0, 0);
+
+    println!("");
+    cli_flow::infoln("# Summary");
+    println!("");
+    println!("{:<width$}  {}", "Host", "Status", width = hostname_width);
+
+    for &(i, ref result) in results {
+        let hostname = format!("{:<width$}", targets[i].label(), width = hostname_width);
+
+        match *result {
+            Ok(HostSyncStatus::Synced) => {
+                synced += 1;
+                println!("{}  {}", hostname, "synced".green());
             }
-        };
-
-        // create ssh session
-        let mut ssh_sess = match Session::new() {
-            Some(s) => s,
-            None => {
-                cli_flow::errorln("Unable to create SSH session.");
-                continue;
+            Ok(HostSyncStatus::Skipped(ref reason)) => {
+                skipped += 1;
+                println!("{}  {} ({})", hostname, "skipped".yellow(), reason);
             }
-        };
-
-        // ssh handshake
-        match ssh_sess.handshake(&ssh_tcp) {
-            Ok(h) => h,
-            Err(e) => {
-                cli_flow::errorln(&e.to_string());
-                continue;
+            Err(ref e) => {
+                failed += 1;
+                println!("{}  {} ({})", hostname, "failed".red(), e);
             }
-        };
-
-        // prompt for remote user
-        if !ssh_config_used {
-            ssh_user = cli_flow::read_line(
-                &format!("SSH User ({}):", ssh_user_default),
-                &ssh_user_default.to_owned(),
-            ).to_owned();
-        } else {
-            cli_flow::infoln(&format!("SSH User: {}", ssh_user));
         }
+    }
 
-        if password_auth {
-            // prompt for password
-            cli_flow::prompt("Password:", false);
-            let password = rpassword::prompt_password_stdout("").unwrap();
-
-            match ssh_sess.userauth_password(&ssh_user, &password) {
-                Ok(t) => {
-                    // drop ssh_password
-                    drop(password);
-                    t
-                }
-                Err(e) => {
-                    cli_flow::errorln(&e.to_string());
-                    // drop passphrase
-                    drop(password);
-                    continue;
-                }
-            };
-        } else {
-            let agent_authed = match userauth_agent(&mut ssh_sess, &ssh_user) {
-                Ok(true) => true,
-                Ok(false) | Err(_) => false,
-            };
+    println!("");
+    cli_flow::infoln(&format!(
+        "{} synced, {} failed, {} skipped",
+        synced, failed, skipped
+    ));
+}
 
-            if !agent_authed {
-                // guess ssh key location
-                let private_key_path = match env::home_dir() {
-                    Some(path) => path.join(".ssh").join("id_rsa"),
-                    None => Path::new("").to_path_buf(),
-                };
-
-                let mut private_key_file_default = private_key_path.to_str().unwrap();
-                let private_key_file = &cli_flow::read_line(
-                    &format!("Private key ({}):", private_key_file_default),
-                    &private_key_file_default.to_owned(),
-                );
-
-                // prompt for passphrase
-                cli_flow::prompt("Passphrase (empty for no passphrase):", false);
-                let private_key_pass = rpassword::prompt_password_stdout("").unwrap();
-
-                // public key auth
-                match ssh_sess.userauth_pubkey_file(
-                    &ssh_user,
-                    None,
-                    Path::new(&private_key_file),
-                    Some(&private_key_pass),
-                ) {
-                    Ok(t) => {
-                        // drop passphrase
-                        drop(private_key_pass);
-                        t
-                    }
-                    Err(e) => {
-                        cli_flow::errorln(&e.to_string());
-                        // drop passphrase
-                        drop(private_key_pass);
-                        continue;
-                    }
-                }
-            }
+// failed hosts don't stop the sync of the others, they keep sync_todo and make it return Err
+pub fn sync(db: &mut Database, options: &SyncOptions) -> Result<(), Box<Error>> {
+    if options.connect.non_interactive {
+        if options.connect.password_auth {
+            cli_flow::errorln("Password authentication is not supported in non-interactive mode");
         }
 
-        // read current authorized_keys from host
-        let mut remote_authorized_keys_file_default = String::new();
+        if !options.yes && !options.dry_run {
+            cli_flow::errorln("Non-interactive mode requires --yes to confirm changes");
+        }
+    }
 
-        if let Ok(mut channel) = ssh_sess.channel_session() {
-            let mut r_get_home = channel.exec("echo $HOME");
+    if options.jobs > 1 && !options.connect.non_interactive {
+        cli_flow::errorln("--jobs requires --non-interactive, prompts of concurrent hosts would mix up");
+    }
+
+    let ssh_config = match ssh_config::get() {
+        Ok(c) => c,
+        Err(e) => {
+            cli_flow::warningln(&e.to_string());
+            SSHConfig::default()
+        }
+    };
 
-            if r_get_home.is_ok() {
-                let mut home = String::new();
-                let r_read = channel.read_to_string(&mut home);
+    // hosts with lapsed grants need a sync to remove them
+    let now = Utc::now();
+    db.expired_grants_sync_todo(&now);
 
-                if r_read.is_ok() {
-                    remote_authorized_keys_file_default = format!(
-                        "{}/.ssh/authorized_keys",
-                        home.trim_right().trim_left().to_owned()
-                    );
-                    channel.wait_close().is_ok();
-                }
-            }
-        };
-
-        // prompt for remote authorized_keys file
-        let mut remote_authorized_keys_file = String::new();
-
-        if yes_authorized_keys_prompt {
-            cli_flow::infoln(&format!(
-                "Remote authorized_keys: {}",
-                remote_authorized_keys_file_default
-            ));
-        } else {
-            remote_authorized_keys_file = cli_flow::read_line(
-                &format!(
-                    "Remote authorized_keys ({}):",
-                    remote_authorized_keys_file_default
-                ),
-                &remote_authorized_keys_file_default,
-            ).to_owned();
+    // accounts of hosts to sync with the authorized_keys they should have
+    let mut targets = Vec::new();
+    for (i, host) in db.hosts.iter().enumerate() {
+        // sync needed for host?
+        if !host.sync_todo {
+            continue;
         }
 
-        let authorized_keys_res = ssh_sess.scp_recv(Path::new(&remote_authorized_keys_file));
-        let mut authorized_keys_remote = Vec::new();
-        let mut authorized_keys_remote_str = "";
-
-        match authorized_keys_res {
-            Ok(r) => {
-                let (mut ch, _stat) = r;
-                ch.read_to_end(&mut authorized_keys_remote).unwrap();
-
-                authorized_keys_remote_str = match str::from_utf8(&authorized_keys_remote) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        cli_flow::warningln(&format!(
-                            "{}: Invalid UTF-8 sequence: {}",
-                            host.hostname, e
-                        ));
-                        continue;
-                    }
-                };
-            }
-            Err(e) => {
-                cli_flow::warningln(&format!(
-                    "Unable to read remote {} - {}",
-                    remote_authorized_keys_file,
-                    e.to_string()
-                ));
-            }
-        };
-
-        // collect authorized_keys to sync ...
-        let mut authorized_keys_sync_vec: Vec<String> = Vec::new();
-
-        // ... 1. on user level
-        for authorized_user_id in &host.authorized_users {
-            for user in &db.users {
-                if &user.user_id == authorized_user_id {
-                    // build e.g.
-                    // # mail@example.com
-                    // ssh-rsa ...
-                    authorized_keys_sync_vec.append(&mut vec![format!(
-                        "# {}\n{}",
-                        authorized_user_id,
-                        String::from(&*user.public_key)
-                    )]);
-                }
-            }
+        for account in db.host_sync_accounts(&host) {
+            let authorized_keys_sync =
+                authorized_keys::render(&db, &host, account.as_ref().map(|a| &**a), &now);
+
+            targets.push(SyncTarget {
+                host_index: i,
+                host: host.clone(),
+                account: account,
+                authorized_keys_sync: authorized_keys_sync,
+            });
         }
+    }
 
-        // ... 2. on group level
-        for authorized_group_id in &host.authorized_user_groups {
-            for group in &db.user_groups {
-                if authorized_group_id == &group.group_id {
-                    for user_id in &group.members {
-                        for user in &db.users {
-                            if user_id == &user.user_id {
-                                authorized_keys_sync_vec.append(&mut vec![format!(
-                                    "# {}\n{}",
-                                    user_id,
-                                    String::from(&*user.public_key)
-                                )]);
-                                break;
-                            }
-                        }
-                    }
-                    break;
-                }
-            }
+    if targets.is_empty() {
+        cli_flow::okln("All hosts up to date. Nothing to sync, bye bye");
+        return Ok(());
+    }
+
+    let mut results = if options.jobs > 1 {
+        // workers pop from the end
+        let targets_todo = targets.iter().cloned().enumerate().rev().collect();
+        sync_hosts_parallel(targets_todo, ssh_config, &options)
+    } else {
+        targets
+            .iter()
+            .enumerate()
+            .map(|(i, target)| (i, sync_host(&target, &ssh_config, &options)))
+            .collect()
+    };
+    results.sort_by_key(|&(i, _)| i);
+
+    // mark as synced once all accounts of a host are, expired grants are gone now
+    for (i, host) in db.hosts.iter_mut().enumerate() {
+        let mut host_results = results
+            .iter()
+            .filter(|&&(t, _)| targets[t].host_index == i)
+            .peekable();
+
+        if host_results.peek().is_none() {
+            continue;
         }
 
-        authorized_keys_sync_vec.sort();
-        authorized_keys_sync_vec.dedup();
-
-        // show diff of authorized_keys of host <-> to sync
-        let authorized_keys_sync_str = format!("{}\n", authorized_keys_sync_vec.join("\n\n"));
-        let Changeset { diffs, .. } =
-            Changeset::new(&authorized_keys_remote_str, &authorized_keys_sync_str, "\n");
-
-        println!("");
-        for i in 0..diffs.len() {
-            match diffs[i] {
-                Difference::Same(ref x) => {
-                    println!("{}", x);
-                }
-                Difference::Add(ref x) => {
-                    println!("{}", format!("+{}", x).green());
-                }
-                Difference::Rem(ref x) => {
-                    println!("{}", format!("-{}", x).red());
-                }
-            }
+        if host_results.all(|&(_, ref r)| match *r {
+            Ok(HostSyncStatus::Synced) => true,
+            _ => false,
+        }) {
+            host.sync_todo = false;
+            host.authorized_users.retain(|g| !g.is_expired(&now));
+            host.authorized_user_groups.retain(|g| !g.is_expired(&now));
         }
+    }
 
-        // sync confirmation
-        if cli_flow::prompt_yes_no(
-            &mut format!(
-                "Verify changes. Do you want to sync to {}? (y/n):",
-                remote_authorized_keys_file
-            ),
-            true,
-        ) == "n"
-        {
-            cli_flow::warningln(&format!("Skipping sync of {} as you told so\n\n", ssh_host));
-            continue;
+    // expired grants of a host group are gone once all its hosts are synced
+    let hosts_synced: Vec<String> = db
+        .hosts
+        .iter()
+        .filter(|h| !h.sync_todo)
+        .map(|h| h.hostname.to_owned())
+        .collect();
+
+    for host_group in &mut db.host_groups {
+        if host_group.hosts.iter().all(|h| hosts_synced.contains(h)) {
+            host_group.authorized_users.retain(|g| !g.is_expired(&now));
+            host_group
+                .authorized_user_groups
+                .retain(|g| !g.is_expired(&now));
         }
+    }
 
-        // sync!
-        let mut remote_authorized_keys_fh = match ssh_sess.scp_send(
-            Path::new(&remote_authorized_keys_file),
-            0o600,
-            authorized_keys_sync_str.len() as u64,
-            None,
-        ) {
-            Ok(r) => r,
-            Err(e) => {
-                cli_flow::errorln(&format!(
-                    "Unable to upload {} - {}",
-                    remote_authorized_keys_file,
-                    &e.to_string()
-                ));
-                return;
-            }
-        };
-
-        match remote_authorized_keys_fh.write(authorized_keys_sync_str.as_bytes()) {
-            Ok(r) => r,
-            Err(e) => {
-                cli_flow::errorln(&format!(
-                    "Unable to upload {} - {}",
-                    remote_authorized_keys_file,
-                    &e.to_string()
-                ));
-                return;
-            }
-        };
+    summary_print(&targets, &results);
 
-        // mark as synced
-        host.sync_todo = false;
+    let failed = results.iter().filter(|&&(_, ref r)| r.is_err()).count();
+    if failed > 0 {
+        return Err(From::from(format!("Sync failed for {} host(s)", failed)));
+    }
 
-        cli_flow::okln(&format!(
-            "Successfully synced to {}\n",
-            remote_authorized_keys_file
-        ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64;
+
+    const PUBLIC_KEY_FOO1: &'static str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAINZ4NANG2PI6O7Ldl7NusZCRgYZ+jMwH8cEE47wpqBKc foo1@example.com";
+
+    fn blob() -> Vec<u8> {
+        base64::decode(PUBLIC_KEY_FOO1.split_whitespace().nth(1).unwrap()).unwrap()
     }
 
-    if !syned_sth {
-        cli_flow::okln("All hosts up to date. Nothing to sync, bye bye");
+    #[test]
+    fn lockout_check_auth() {
+        let path = "/root/.ssh/authorized_keys";
+        let auth = Auth::PublicKey(blob());
+
+        assert!(lockout_check(&auth, true, path, PUBLIC_KEY_FOO1).is_ok());
+        assert!(lockout_check(&auth, true, path, "").is_err());
+
+        // authorized_keys of another account or password login
+        assert!(lockout_check(&auth, false, path, "").is_ok());
+        assert!(lockout_check(&Auth::Password, true, path, "").is_ok());
+    }
+
+    #[test]
+    fn lockout_check_unknown_auth() {
+        let auth = Auth::Unknown("Unable to read /root/.ssh/id_ed25519.pub".to_owned());
+
+        // even an authorized_keys with keys is refused, the key of the session is not known
+        let e = lockout_check(&auth, true, "/root/.ssh/authorized_keys", PUBLIC_KEY_FOO1)
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Unable to check if the key used to connect stays in /root/.ssh/authorized_keys - \
+             Unable to read /root/.ssh/id_ed25519.pub"
+        );
+
+        assert!(lockout_check(&auth, false, "/root/.ssh/authorized_keys", "").is_ok());
     }
 }
@@ -1,14 +1,12 @@
+use chrono::Utc;
+use authorized_keys;
 use cli_flow;
-use database::{Database, User};
+use database::{account_label, Database, Grant, User, UserPublicKey};
+use public_key;
 use std::io;
+use subcommand_hostgroup;
 
-pub fn add(db: &mut Database, user_id: &str) {
-    // check user is not present
-    if db.user_get(user_id).is_some() {
-        cli_flow::errorln(&format!("User {} already exists", user_id));
-    }
-
-    // read public key
+fn read_public_key(db: &Database, user_id: &str) -> String {
     cli_flow::promptln(&format!(
         "Paste the public key of {} and press the Enter key:",
         user_id
@@ -20,15 +18,39 @@ pub fn add(db: &mut Database, user_id: &str) {
         .ok()
         .expect("Couldn't read public key");
 
-    // TODO:; daring assumption, validate...
-    if !public_key.starts_with("ssh-") {
-        cli_flow::errorln("Invalid public ssh key format")
+    let public_key = match public_key::parse(&public_key) {
+        Ok(k) => k,
+        Err(e) => {
+            cli_flow::errorln(&format!("Invalid public ssh key - {}", e.to_string()));
+            return String::new();
+        }
+    };
+
+    if let Err(e) = db.key_policy.check(&public_key) {
+        cli_flow::errorln(&format!("Public key violates key policy - {}", e));
     }
 
+    public_key.to_string()
+}
+
+pub fn add(db: &mut Database, user_id: &str) {
+    // check user is not present
+    if db.user_get(user_id).is_some() {
+        cli_flow::errorln(&format!("User {} already exists", user_id));
+    }
+
+    // read public key
+    let public_key = read_public_key(db, user_id);
+
     // add new user
     let mut user_new = vec![User {
         user_id: user_id.to_owned(),
-        public_key: public_key.trim_right().trim_left().to_owned(),
+        public_keys: vec![UserPublicKey {
+            key: public_key,
+            label: String::from("default"),
+            added_at: format!("{}", Utc::now()),
+        }],
+        public_key: None,
     }];
 
     db.users.append(&mut user_new);
@@ -41,12 +63,20 @@ pub fn remove(db: &mut Database, user_id: &str) {
         cli_flow::errorln(&format!("User {} not known", user_id));
     }
 
+    // hosts of the user, granted directly, through host groups or user groups, need a sync
+    db.user_hosts_sync_todo(user_id);
+
     // rm user
     db.users.retain(|u| u.user_id != user_id);
 
     // delete user from hosts.authorized_users
     for host in &mut db.hosts {
-        host.authorized_users.retain(move |u| u != user_id);
+        host.authorized_users.retain(move |u| u.id != user_id);
+    }
+
+    // delete user from host_groups.authorized_users
+    for host_group in &mut db.host_groups {
+        host_group.authorized_users.retain(move |u| u.id != user_id);
     }
 
     // delete user from user_groups.members
@@ -73,66 +103,288 @@ pub fn list(db: &mut Database, user_id_filter: &str, print_raw: bool) {
             "{}",
             (0..user.user_id.len()).map(|_| "=").collect::<String>()
         );
+
+        println!("\n## Public Keys");
+        for public_key in &user.public_keys {
+            let fingerprint = match public_key::parse(&public_key.key) {
+                Ok(k) => k.fingerprint_sha256(),
+                Err(_) => String::from("invalid key"),
+            };
+            println!(
+                "* {} {} (added {})",
+                public_key.label, fingerprint, public_key.added_at
+            );
+        }
     }
 
     println!("");
 }
 
-pub fn grant(db: &mut Database, user_id: &str, hostname: &str) {
+pub fn grant(
+    db: &mut Database,
+    user_id: &str,
+    hostname: &str,
+    account: Option<&str>,
+    options: &[&str],
+    expires_at: Option<String>,
+) {
+    let options = match authorized_keys::options_normalize(options) {
+        Ok(o) => o,
+        Err(e) => {
+            cli_flow::errorln(&e.to_string());
+            return;
+        }
+    };
+
+    let host_label = account_label(hostname, account);
+
+    // already granted => the grant is replaced, options and expiry included
+    let grant = Grant {
+        id: String::from(user_id),
+        options: options,
+        expires_at: expires_at,
+        account: account.map(|a| a.to_owned()),
+    };
+
+    // a host or a host group
     if let Some(host) = db.host_get(hostname) {
+        if let Some(account) = account {
+            if !host.has_account(account) {
+                cli_flow::errorln(&format!(
+                    "Host {} has no account {}, add it with: host {} account add {}",
+                    hostname, account, hostname, account
+                ));
+            }
+        }
+
         if let Some(user) = db.user_get(user_id) {
-            if db.is_user_granted(&user, &host) {
+            if host.authorized_users.contains(&grant) {
                 cli_flow::errorln(&format!(
                     "{} already granted to access {}",
-                    user.user_id, hostname
+                    user.user_id, host_label
+                ));
+            }
+        } else {
+            cli_flow::errorln(&format!("User {} not known", user_id));
+        }
+    } else if let Some(host_group) = db.host_group_get(hostname) {
+        subcommand_hostgroup::account_check(&db, &host_group, account);
+
+        if let Some(user) = db.user_get(user_id) {
+            if host_group.authorized_users.contains(&grant) {
+                cli_flow::errorln(&format!(
+                    "{} already granted to access host group {}",
+                    user.user_id, host_label
                 ));
             }
         } else {
             cli_flow::errorln(&format!("User {} not known", user_id));
         }
     } else {
-        cli_flow::errorln(&format!("Hostname {} not known", hostname));
+        cli_flow::errorln(&format!("Hostname or host group {} not known", hostname));
     }
 
     // at this point it's save to mut db.host...
-    {
-        let host = db.host_get_mut(hostname).unwrap();
-        host.authorized_users
-            .append(&mut vec![String::from(user_id)]);
+    if let Some(host) = db.host_get_mut(hostname) {
+        Grant::set(&mut host.authorized_users, grant);
         host.sync_todo = true;
+
+        cli_flow::okln(&format!(
+            "Successfully granted user {} to host {}",
+            user_id, host_label
+        ));
+        return;
     }
 
+    // ... or db.host_group
+    {
+        let host_group = db.host_group_get_mut(hostname).unwrap();
+        Grant::set(&mut host_group.authorized_users, grant);
+    }
+    db.host_group_hosts_sync_todo(hostname);
+
     cli_flow::okln(&format!(
-        "Successfully granted user {} to host {}",
-        user_id, hostname
+        "Successfully granted user {} to host group {}",
+        user_id, host_label
     ));
 }
 
-pub fn revoke(db: &mut Database, user_id: &str, hostname: &str) {
+pub fn revoke(db: &mut Database, user_id: &str, hostname: &str, account: Option<&str>) {
+    let host_label = account_label(hostname, account);
+
+    // a host or a host group
     if let Some(host) = db.host_get(hostname) {
         if let Some(user) = db.user_get(user_id) {
-            if !db.is_user_granted(&user, &host) {
+            if !db.is_user_granted(&user, &host, account) {
                 cli_flow::errorln(&format!(
                     "{} is not granted to access {}",
-                    user.user_id, hostname
+                    user.user_id, host_label
+                ));
+            }
+        } else {
+            cli_flow::errorln(&format!("User {} not known", user_id));
+        }
+    } else if let Some(host_group) = db.host_group_get(hostname) {
+        if let Some(user) = db.user_get(user_id) {
+            if !db.is_user_granted_host_group(&user, &host_group, account) {
+                cli_flow::errorln(&format!(
+                    "{} is not granted to access host group {}",
+                    user.user_id, host_label
                 ));
             }
         } else {
             cli_flow::errorln(&format!("User {} not known", user_id));
         }
     } else {
-        cli_flow::errorln(&format!("Hostname {} not known", hostname));
+        cli_flow::errorln(&format!("Hostname or host group {} not known", hostname));
     }
 
     // at this point it's save to mut db.host...
-    {
-        let host = db.host_get_mut(hostname).unwrap();
-        host.authorized_users.retain(|u| u != user_id);
+    if let Some(host) = db.host_get_mut(hostname) {
+        host.authorized_users
+            .retain(|g| g.id != user_id || !g.is_account(account));
         host.sync_todo = true;
+
+        cli_flow::okln(&format!(
+            "Successfully revoked user {} from host {}",
+            user_id, host_label
+        ));
+        return;
+    }
+
+    // ... or db.host_group
+    db.host_group_hosts_sync_todo(hostname);
+    {
+        let host_group = db.host_group_get_mut(hostname).unwrap();
+        host_group
+            .authorized_users
+            .retain(|g| g.id != user_id || !g.is_account(account));
     }
 
     cli_flow::okln(&format!(
-        "Successfully revoked user {} from host {}",
-        user_id, hostname
+        "Successfully revoked user {} from host group {}",
+        user_id, host_label
     ));
 }
+
+pub fn key_add(db: &mut Database, user_id: &str, label: &str) {
+    if let Some(user) = db.user_get(user_id) {
+        if user.public_key_get(label).is_some() {
+            cli_flow::errorln(&format!(
+                "User {} already has a public key labeled {}",
+                user_id, label
+            ));
+        }
+    } else {
+        cli_flow::errorln(&format!("User {} not known", user_id));
+    }
+
+    // read public key
+    let public_key = read_public_key(db, user_id);
+
+    if db.user_get(user_id).unwrap().has_public_key(&public_key) {
+        cli_flow::errorln(&format!(
+            "Public key is already known for user {}",
+            user_id
+        ));
+    }
+
+    // at this point it's save to mut db.user...
+    {
+        let user = db.user_get_mut(user_id).unwrap();
+        user.public_keys.append(&mut vec![UserPublicKey {
+            key: public_key,
+            label: label.to_owned(),
+            added_at: format!("{}", Utc::now()),
+        }]);
+    }
+
+    db.user_hosts_sync_todo(user_id);
+
+    cli_flow::okln(&format!(
+        "Successfully added public key {} to user {}",
+        label, user_id
+    ));
+}
+
+pub fn key_remove(db: &mut Database, user_id: &str, label: &str) {
+    if let Some(user) = db.user_get(user_id) {
+        if user.public_key_get(label).is_none() {
+            cli_flow::errorln(&format!(
+                "User {} has no public key labeled {}",
+                user_id, label
+            ));
+        }
+
+        if user.public_keys.len() == 1 {
+            cli_flow::errorln(&format!(
+                "Public key {} is the last key of user {}. Remove the user instead",
+                label, user_id
+            ));
+        }
+    } else {
+        cli_flow::errorln(&format!("User {} not known", user_id));
+    }
+
+    // at this point it's save to mut db.user...
+    {
+        let user = db.user_get_mut(user_id).unwrap();
+        user.public_keys.retain(|k| k.label != label);
+    }
+
+    db.user_hosts_sync_todo(user_id);
+
+    cli_flow::okln(&format!(
+        "Successfully removed public key {} from user {}",
+        label, user_id
+    ));
+}
+
+pub fn key_list(db: &mut Database, user_id: &str) {
+    let user = match db.user_get(user_id) {
+        Some(u) => u,
+        None => {
+            cli_flow::errorln(&format!("User {} not known", user_id));
+            return;
+        }
+    };
+
+    for public_key in &user.public_keys {
+        println!("\n{}", public_key.label);
+        println!(
+            "{}",
+            (0..public_key.label.len()).map(|_| "=").collect::<String>()
+        );
+        println!("Added: {}", public_key.added_at);
+        match public_key::parse(&public_key.key) {
+            Ok(k) => {
+                println!("Type: {} ({} bits)", k.algorithm, k.bits);
+                println!("Fingerprint: {}", k.fingerprint_sha256());
+                println!("Fingerprint: {}", k.fingerprint_md5());
+            }
+            Err(e) => cli_flow::warningln(&format!("Invalid public key - {}", e.to_string())),
+        }
+        println!("{}", public_key.key);
+    }
+
+    println!("");
+}
+
+pub fn find_by_fingerprint(db: &mut Database, fingerprint: &str) {
+    let mut found = false;
+
+    for user in &db.users {
+        for public_key in &user.public_keys {
+            if let Ok(k) = public_key::parse(&public_key.key) {
+                if k.matches_fingerprint(fingerprint) {
+                    println!("{} ({})", user.user_id, public_key.label);
+                    found = true;
+                }
+            }
+        }
+    }
+
+    if !found {
+        cli_flow::errorln(&format!("No public key with fingerprint {} found", fingerprint));
+    }
+}
@@ -0,0 +1,163 @@
+use libc;
+use ssh2::{self, Session};
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc;
+use std::thread;
+
+// libssh2 reports EAGAIN of non-blocking sessions as io::ErrorKind::Other
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+
+fn would_block(e: &io::Error) -> bool {
+    if e.kind() == io::ErrorKind::WouldBlock {
+        return true;
+    }
+
+    e.get_ref()
+        .and_then(|e| e.downcast_ref::<ssh2::Error>())
+        .map(|e| e.code() == LIBSSH2_ERROR_EAGAIN)
+        .unwrap_or(false)
+}
+
+// blocks until one of the streams has data to read, returns if local has
+fn poll_read(local: &TcpStream, ssh_tcp: &TcpStream) -> io::Result<bool> {
+    let mut fds = [
+        libc::pollfd {
+            fd: local.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: ssh_tcp.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+
+    loop {
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } >= 0 {
+            return Ok(fds[0].revents != 0);
+        }
+
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+// copies between the local stream and the channel until one of them is closed
+fn pump<C: Read + Write>(
+    ssh_sess: &Session,
+    ssh_tcp: &TcpStream,
+    local: &mut TcpStream,
+    channel: &mut C,
+) -> io::Result<()> {
+    let mut buf = [0; 16384];
+
+    loop {
+        // libssh2 may hold data already read from the socket, drain it before polling
+        ssh_sess.set_blocking(false);
+        loop {
+            match channel.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(n) => local.write_all(&buf[..n])?,
+                Err(ref e) if would_block(e) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if !poll_read(&local, &ssh_tcp)? {
+            continue;
+        }
+
+        match local.read(&mut buf)? {
+            0 => return Ok(()),
+            n => {
+                // blocking, libssh2 waits for the window of the channel
+                ssh_sess.set_blocking(true);
+                channel.write_all(&buf[..n])?;
+            }
+        }
+    }
+}
+
+// forwards the first connection to the listener from peer through the channel to host:port
+fn tunnel(
+    ssh_sess: &Session,
+    ssh_tcp: &TcpStream,
+    listener: TcpListener,
+    host: &str,
+    port: u16,
+    tx: mpsc::Sender<Result<(), String>>,
+    peer_rx: mpsc::Receiver<SocketAddr>,
+) {
+    let mut channel = match ssh_sess.channel_direct_tcpip(&host, port, None) {
+        Ok(c) => c,
+        Err(e) => {
+            tx.send(Err(format!("Unable to open a tunnel to {}:{} - {}", host, port, e)))
+                .is_ok();
+            return;
+        }
+    };
+    tx.send(Ok(())).is_ok();
+
+    let peer = match peer_rx.recv() {
+        Ok(p) => p,
+        Err(_) => return,
+    };
+
+    // any local process can connect to the port, only our own stream gets the tunnel
+    let mut local = loop {
+        match listener.accept() {
+            Ok((s, addr)) => if addr == peer {
+                break s;
+            },
+            Err(_) => return,
+        }
+    };
+    drop(listener);
+
+    pump(&ssh_sess, &ssh_tcp, &mut local, &mut channel).is_ok();
+}
+
+// opens a direct-tcpip channel to host:port through an authenticated session and returns a
+// local stream connected to it, libssh2 can only handshake over a socket
+pub fn forward(
+    ssh_sess: Session,
+    ssh_tcp: TcpStream,
+    host: &str,
+    port: u16,
+) -> Result<TcpStream, Box<Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let local_addr = listener.local_addr()?;
+    let host = host.to_owned();
+
+    let (tx, rx) = mpsc::channel();
+    let (peer_tx, peer_rx) = mpsc::channel();
+
+    // the thread owns the session, it ends when the tunnel is closed
+    thread::spawn(move || {
+        tunnel(&ssh_sess, &ssh_tcp, listener, &host, port, tx, peer_rx);
+
+        // the session goes first, it still uses the socket on disconnect
+        drop(ssh_sess);
+        drop(ssh_tcp);
+    });
+
+    match rx.recv() {
+        Ok(Ok(())) => {
+            let local = TcpStream::connect(local_addr)?;
+
+            if peer_tx.send(local.local_addr()?).is_err() {
+                return Err(From::from("Tunnel closed unexpectedly"));
+            }
+
+            Ok(local)
+        }
+        Ok(Err(e)) => Err(From::from(e)),
+        Err(_) => Err(From::from("Tunnel closed unexpectedly")),
+    }
+}
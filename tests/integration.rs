@@ -1,9 +1,17 @@
 extern crate assert_cli;
 
 use std::fs;
+use std::io::{Read, Write};
 use std::panic;
 use std::path::{Path, PathBuf};
 
+const PUBLIC_KEY_FOO1: &'static str =
+    "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAINZ4NANG2PI6O7Ldl7NusZCRgYZ+jMwH8cEE47wpqBKc foo1@example.com";
+const PUBLIC_KEY_FOO2: &'static str =
+    "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBDfkVLpgDFHkFr46AB8rwugPp5Zle22Rb3s1GKIAmX2P45hqASWV3iSy4WPnjZicRsMo+pgGYA9fW5qX4NJAkU4= foo2@example.com";
+const PUBLIC_KEY_FOO3: &'static str =
+    "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAILP+b1okxbE9KhagzVFpFi8WGRXYjtc7nwlS0lHg/oCX foo3@example.com";
+
 fn tests_dir() -> PathBuf {
     Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
 }
@@ -39,6 +47,23 @@ fn assert_cli_bin(test_id: u32) -> assert_cli::Assert {
     ])
 }
 
+// grants can't be given with a date in the past, move the date of a saved grant there
+fn grant_expire(test_id: u32, date: &str) {
+    let mut database = String::new();
+    fs::File::open(settings_fixtures_copy(test_id))
+        .unwrap()
+        .read_to_string(&mut database)
+        .unwrap();
+    fs::File::create(settings_fixtures_copy(test_id))
+        .unwrap()
+        .write_all(
+            database
+                .replace(&format!("{}T", date), "2018-01-01T")
+                .as_bytes(),
+        )
+        .unwrap();
+}
+
 fn run_test<T>(test_id: u32, test: T) -> ()
 where
     T: FnOnce() -> () + panic::UnwindSafe,
@@ -261,14 +286,14 @@ fn user_add_remove() {
         // user foo1 add
         assert_cli_bin(test_id)
             .with_args(&["user", "foo1", "add"])
-            .stdin("ssh-123")
+            .stdin(PUBLIC_KEY_FOO1)
             .succeeds()
             .unwrap();
 
         // user foo2 add
         assert_cli_bin(test_id)
             .with_args(&["user", "foo2", "add"])
-            .stdin("ssh-456")
+            .stdin(PUBLIC_KEY_FOO2)
             .succeeds()
             .unwrap();
 
@@ -301,9 +326,9 @@ fn user_add_remove() {
             .with_args(&["user", "foo1", "list", "--raw"])
             .succeeds()
             .stdout()
-            .contains("ssh-123")
+            .contains("foo1@example.com")
             .stdout()
-            .doesnt_contain("ssh-456")
+            .doesnt_contain("foo2@example.com")
             .unwrap();
 
         // user foo1 remove
@@ -338,14 +363,14 @@ fn user_add_duplicate_deny() {
         // user foo add
         assert_cli_bin(test_id)
             .with_args(&["user", "foo1", "add"])
-            .stdin("ssh-")
+            .stdin(PUBLIC_KEY_FOO1)
             .succeeds()
             .unwrap();
 
         // user foo add
         assert_cli_bin(test_id)
             .with_args(&["user", "foo1", "add"])
-            .stdin("ssh-")
+            .stdin(PUBLIC_KEY_FOO1)
             .fails()
             .unwrap();
 
@@ -457,21 +482,21 @@ fn user_grant_revoke() {
         // user foo1 add
         assert_cli_bin(test_id)
             .with_args(&["user", "foo1", "add"])
-            .stdin("ssh-")
+            .stdin(PUBLIC_KEY_FOO1)
             .succeeds()
             .unwrap();
 
         // user foo2 add
         assert_cli_bin(test_id)
             .with_args(&["user", "foo2", "add"])
-            .stdin("ssh-")
+            .stdin(PUBLIC_KEY_FOO2)
             .succeeds()
             .unwrap();
 
         // user foo3 add
         assert_cli_bin(test_id)
             .with_args(&["user", "foo3", "add"])
-            .stdin("ssh-")
+            .stdin(PUBLIC_KEY_FOO3)
             .succeeds()
             .unwrap();
 
@@ -612,3 +637,1279 @@ fn user_grant_revoke() {
             .unwrap();
     })
 }
+
+#[test]
+fn user_keys_add_remove() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // user foo1 add
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "add"])
+            .stdin(PUBLIC_KEY_FOO1)
+            .succeeds()
+            .unwrap();
+
+        // user foo1 key add laptop
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "key", "add", "laptop"])
+            .stdin(PUBLIC_KEY_FOO2)
+            .succeeds()
+            .unwrap();
+
+        // user foo1 key add laptop (fail, label exists)
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "key", "add", "laptop"])
+            .stdin(PUBLIC_KEY_FOO3)
+            .fails()
+            .unwrap();
+
+        // user foo1 key add workstation (fail, key exists)
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "key", "add", "workstation"])
+            .stdin(PUBLIC_KEY_FOO2)
+            .fails()
+            .unwrap();
+
+        // user foo1 key add workstation (fail, key exists with another comment)
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "key", "add", "workstation"])
+            .stdin(&*PUBLIC_KEY_FOO2.replace("foo2@example.com", "foo2@workstation"))
+            .fails()
+            .unwrap();
+
+        // user foo1 key list
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "key", "list"])
+            .succeeds()
+            .stdout()
+            .contains("default")
+            .stdout()
+            .contains("foo1@example.com")
+            .stdout()
+            .contains("laptop")
+            .stdout()
+            .contains("foo2@example.com")
+            .unwrap();
+
+        // user foo1 key remove default
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "key", "remove", "default"])
+            .succeeds()
+            .unwrap();
+
+        // user foo1 key remove laptop (fail, last key)
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "key", "remove", "laptop"])
+            .fails()
+            .unwrap();
+
+        // user foo1 key list
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "key", "list"])
+            .succeeds()
+            .stdout()
+            .doesnt_contain("foo1@example.com")
+            .stdout()
+            .contains("foo2@example.com")
+            .unwrap();
+
+        // single public_key of fixture user is migrated
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "key", "list"])
+            .succeeds()
+            .stdout()
+            .contains("default")
+            .stdout()
+            .contains("existing@example.com")
+            .unwrap();
+    })
+}
+
+#[test]
+fn user_add_invalid_key_deny() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // unknown algorithm
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "add"])
+            .stdin("ssh-foo bar")
+            .fails()
+            .unwrap();
+
+        // algorithm prefix doesn't match key data
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "add"])
+            .stdin(PUBLIC_KEY_FOO1.replacen("ssh-ed25519", "ssh-rsa", 1).as_str())
+            .fails()
+            .unwrap();
+
+        // truncated key data
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "add"])
+            .stdin(&PUBLIC_KEY_FOO1[..40])
+            .fails()
+            .unwrap();
+
+        // ecdsa key
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo2", "add"])
+            .stdin(PUBLIC_KEY_FOO2)
+            .succeeds()
+            .unwrap();
+
+        // user foo2 key list
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo2", "key", "list"])
+            .succeeds()
+            .stdout()
+            .contains("ecdsa-sha2-nistp256 (256 bits)")
+            .unwrap();
+    })
+}
+
+#[test]
+fn user_find_by_fingerprint() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // user foo1 add
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "add"])
+            .stdin(PUBLIC_KEY_FOO1)
+            .succeeds()
+            .unwrap();
+
+        // user list shows SHA256 fingerprints
+        assert_cli_bin(test_id)
+            .with_args(&["user", "list"])
+            .succeeds()
+            .stdout()
+            .contains("SHA256:a5lcfVLkzdMXMaOPcon5tzlRKbyidKwb6TIZpBUcidM")
+            .stdout()
+            .contains("SHA256:eoZq1wdebGW7U3MaQdgX5AzVZmgbe0R7ypo3KAAXK7k")
+            .unwrap();
+
+        // user find-by-fingerprint SHA256:...
+        assert_cli_bin(test_id)
+            .with_args(&[
+                "user",
+                "find-by-fingerprint",
+                "SHA256:a5lcfVLkzdMXMaOPcon5tzlRKbyidKwb6TIZpBUcidM",
+            ])
+            .succeeds()
+            .stdout()
+            .contains("foo1")
+            .stdout()
+            .doesnt_contain("existing-user")
+            .unwrap();
+
+        // user find-by-fingerprint MD5:...
+        assert_cli_bin(test_id)
+            .with_args(&[
+                "user",
+                "find-by-fingerprint",
+                "MD5:a9:d1:46:e3:61:f6:5a:5a:ff:6f:3b:d9:8b:79:12:d6",
+            ])
+            .succeeds()
+            .stdout()
+            .contains("existing-user")
+            .unwrap();
+
+        // user find-by-fingerprint (unknown)
+        assert_cli_bin(test_id)
+            .with_args(&["user", "find-by-fingerprint", "SHA256:unknown"])
+            .fails()
+            .unwrap();
+    })
+}
+
+#[test]
+fn audit_keys() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // RSA key < 2048 bits
+        assert_cli_bin(test_id)
+            .with_args(&["user", "weak", "add"])
+            .stdin(
+                "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQDLV6zYJDhcLMKPMMG7xQ2OOyqnDqas5RJiRjDVVDXj0zT0vWVHtoMaFl2R5tY0twcZt7t6U9Cldq8EirF7Eq6wkI87jr9z5jVIlCpiqXFY9+GqvlKAotH1r2k5EDFDyoYJ9une1aK3AF0boaRU6ApJEthIEkFGsDQfWFNc10cFrQ== weak@example.com",
+            )
+            .fails()
+            .unwrap();
+
+        // DSA key
+        assert_cli_bin(test_id)
+            .with_args(&["user", "weak", "add"])
+            .stdin(
+                "ssh-dss AAAAB3NzaC1kc3MAAACBAOBIC5ZsH58CMVG0S+zkZftHcopLOAmuD9QHYfq+ZdzCtC41TXVi1sKqImBu2kGg1pe6JTDublbA4D5AW8ZrwkqerFTiOWLAGUxTeSZjnjXED9Q0NtIQuxmRaX1EQcMd8y3B6MABSnR4EKY+Ah9zqrGcyE6FRbaweqNcS21DAIgXAAAAFQCbp1PFBFdtr6zJ5RWLSypJSNaVrQAAAIBCcKUvfrFXQdQ/4g8DZOyWsqLYxr5u1ai3cUeSS6uvzuISkZZ26vLThbH42KnYK3k/Yo1/R8xRk8vFgvQPBmurJW6ZjnPefgWQQKXPi+mXRUvrWRLOiunvUXFXQYHZ2iWgudlDy6iGmy17qBxveqvvNGy3Zm/rdfIGfLOrmDWSXAAAAIEAtZBX7leOSbPop7Xl/r91Ds6nS6z50mwwvyHDI18Ja8n7YqgTkYJfrOaOfVDn1gi0JRAuaYfla0NbAHDV9lvBG+gCTE2/gvL4g9mJAc0seF6/T1EJ3uuRAzp1hn7kb4kphQsf6SyZAwYxzlBph2XeAkQNP0rY3+PHBveFANFS1Vs= dsa@example.com",
+            )
+            .fails()
+            .unwrap();
+
+        // audit keys
+        assert_cli_bin(test_id)
+            .with_args(&["audit", "keys"])
+            .succeeds()
+            .unwrap();
+
+        // raise min_rsa_bits in the saved database
+        let mut database = String::new();
+        fs::File::open(settings_fixtures_copy(test_id))
+            .unwrap()
+            .read_to_string(&mut database)
+            .unwrap();
+        fs::File::create(settings_fixtures_copy(test_id))
+            .unwrap()
+            .write_all(
+                database
+                    .replace("\"min_rsa_bits\": 2048", "\"min_rsa_bits\": 4096")
+                    .as_bytes(),
+            )
+            .unwrap();
+
+        // audit keys (existing-user has a 2048 bits RSA key)
+        assert_cli_bin(test_id)
+            .with_args(&["audit", "keys"])
+            .fails()
+            .stdout()
+            .contains("existing-user")
+            .unwrap();
+    })
+}
+
+#[test]
+fn grant_options() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // user foo1 add
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "add"])
+            .stdin(PUBLIC_KEY_FOO1)
+            .succeeds()
+            .unwrap();
+
+        // group dev-ops add
+        assert_cli_bin(test_id)
+            .with_args(&["group", "dev-ops", "add"])
+            .succeeds()
+            .unwrap();
+
+        // unknown option
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "grant", "existing", "--option", "foo"])
+            .fails()
+            .unwrap();
+
+        // flag option with value
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "grant", "existing", "--option", "no-pty=yes"])
+            .fails()
+            .unwrap();
+
+        // user foo1 grant existing --option from=10.0.0.0/8 --option no-pty
+        assert_cli_bin(test_id)
+            .with_args(&[
+                "user",
+                "foo1",
+                "grant",
+                "existing",
+                "--option",
+                "from=10.0.0.0/8",
+                "--option",
+                "no-pty",
+            ])
+            .succeeds()
+            .unwrap();
+
+        // group dev-ops grant existing -o restrict
+        assert_cli_bin(test_id)
+            .with_args(&["group", "dev-ops", "grant", "existing", "-o", "restrict"])
+            .succeeds()
+            .unwrap();
+
+        // host list
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .contains("foo1 [from=\"10.0.0.0/8\",no-pty]")
+            .stdout()
+            .contains("dev-ops [restrict]")
+            .unwrap();
+
+        // grant again with other options updates the options
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "grant", "existing", "-o", "no-agent-forwarding"])
+            .succeeds()
+            .unwrap();
+
+        // host list
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .contains("foo1 [no-agent-forwarding]")
+            .stdout()
+            .doesnt_contain("no-pty")
+            .unwrap();
+
+        // grant again with the same options
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "grant", "existing", "-o", "no-agent-forwarding"])
+            .fails()
+            .unwrap();
+
+        // grant again without options clears the options
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "grant", "existing"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .doesnt_contain("no-agent-forwarding")
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["user", "foo1", "grant", "existing"])
+            .fails()
+            .unwrap();
+
+        // plain user id grant of the fixture is migrated
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .contains("existing-user")
+            .unwrap();
+    })
+}
+
+#[test]
+fn grant_expiry() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // group dev-ops add
+        assert_cli_bin(test_id)
+            .with_args(&["group", "dev-ops", "add"])
+            .succeeds()
+            .unwrap();
+
+        // nothing expired
+        assert_cli_bin(test_id)
+            .with_args(&["expired"])
+            .succeeds()
+            .stdout()
+            .doesnt_contain("existing.example.com")
+            .unwrap();
+
+        // invalid date
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "grant", "existing", "--until", "tomorrow"])
+            .fails()
+            .unwrap();
+
+        // invalid duration
+        assert_cli_bin(test_id)
+            .with_args(&["group", "dev-ops", "grant", "existing", "--for", "7y"])
+            .fails()
+            .unwrap();
+
+        // --until and --for conflict
+        assert_cli_bin(test_id)
+            .with_args(&[
+                "group",
+                "dev-ops",
+                "grant",
+                "existing",
+                "--for",
+                "7d",
+                "--until",
+                "2018-01-01",
+            ])
+            .fails()
+            .unwrap();
+
+        // date in the past
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "grant", "existing", "--until", "2018-01-01"])
+            .fails()
+            .stdout()
+            .contains("Date 2018-01-01 is in the past")
+            .unwrap();
+
+        // already granted existing-user
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "grant", "existing", "--until", "2099-01-01"])
+            .succeeds()
+            .unwrap();
+
+        // group dev-ops grant existing --for 7d
+        assert_cli_bin(test_id)
+            .with_args(&["group", "dev-ops", "grant", "existing", "--for", "7d"])
+            .succeeds()
+            .unwrap();
+
+        // let the grant of existing-user expire in the saved database
+        grant_expire(test_id, "2099-01-01");
+
+        // host list
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .contains("existing-user (expired 2018-01-01T00:00:00+00:00)")
+            .stdout()
+            .contains("dev-ops (until ")
+            .unwrap();
+
+        // expired
+        assert_cli_bin(test_id)
+            .with_args(&["expired"])
+            .succeeds()
+            .stdout()
+            .contains("existing-user")
+            .stdout()
+            .doesnt_contain("dev-ops")
+            .unwrap();
+
+        // grant again without expiry clears the expiry
+        assert_cli_bin(test_id)
+            .with_args(&["group", "dev-ops", "grant", "existing"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .doesnt_contain("dev-ops (until ")
+            .unwrap();
+    })
+}
+
+#[test]
+fn host_managed_block() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // unknown host
+        assert_cli_bin(test_id)
+            .with_args(&["host", "unknown.example.com", "managed-block", "on"])
+            .fails()
+            .unwrap();
+
+        // invalid state
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "managed-block", "maybe"])
+            .fails()
+            .unwrap();
+
+        // host existing managed-block on
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "managed-block", "on"])
+            .succeeds()
+            .unwrap();
+
+        // host list --raw
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list", "--raw"])
+            .succeeds()
+            .stdout()
+            .contains("managed_block: true")
+            .unwrap();
+
+        // host existing managed-block off
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "managed-block", "off"])
+            .succeeds()
+            .unwrap();
+
+        // host list --raw
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list", "--raw"])
+            .succeeds()
+            .stdout()
+            .contains("managed_block: false")
+            .unwrap();
+    })
+}
+
+#[test]
+fn sync_non_interactive_deny() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // --non-interactive without --yes
+        assert_cli_bin(test_id)
+            .with_args(&["sync", "--non-interactive"])
+            .fails()
+            .stdout()
+            .contains("requires --yes")
+            .unwrap();
+
+        // --non-interactive with password authentication
+        assert_cli_bin(test_id)
+            .with_args(&["sync", "--non-interactive", "--yes", "--password"])
+            .fails()
+            .stdout()
+            .contains("Password authentication is not supported")
+            .unwrap();
+
+        // --jobs without --non-interactive
+        assert_cli_bin(test_id)
+            .with_args(&["sync", "--jobs", "4"])
+            .fails()
+            .stdout()
+            .contains("--jobs requires --non-interactive")
+            .unwrap();
+
+        // invalid --jobs
+        assert_cli_bin(test_id)
+            .with_args(&["sync", "--non-interactive", "--yes", "--jobs", "0"])
+            .fails()
+            .stdout()
+            .contains("--jobs expects a number greater than 0")
+            .unwrap();
+    })
+}
+
+#[test]
+fn sync_dry_run() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // unreachable host
+        assert_cli_bin(test_id)
+            .with_args(&["host", "localhost:1", "add"])
+            .succeeds()
+            .unwrap();
+
+        // expired grant, removed by a sync but not by a dry run
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "grant", "localhost:1", "--until", "2099-01-01"])
+            .succeeds()
+            .unwrap();
+
+        grant_expire(test_id, "2099-01-01");
+
+        // a dry run doesn't need --yes in non-interactive mode, every host is tried
+        assert_cli_bin(test_id)
+            .with_args(&[
+                "sync",
+                "--dry-run",
+                "--non-interactive",
+                "--ssh-user",
+                "root",
+            ])
+            .fails()
+            .stdout()
+            .contains("# Syncing host localhost:1...")
+            .stdout()
+            .contains("Sync failed for 2 host(s)")
+            .unwrap();
+
+        // nothing is marked synced, the expired grant is kept
+        let mut database = String::new();
+        fs::File::open(settings_fixtures_copy(test_id))
+            .unwrap()
+            .read_to_string(&mut database)
+            .unwrap();
+        assert_eq!(database.matches("\"sync_todo\": true").count(), 2);
+        assert!(database.contains("2018-01-01T00:00:00+00:00"));
+    })
+}
+
+#[test]
+fn check_non_interactive_deny() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // --non-interactive with password authentication
+        assert_cli_bin(test_id)
+            .with_args(&["check", "--non-interactive", "--password"])
+            .fails()
+            .stdout()
+            .contains("Password authentication is not supported")
+            .unwrap();
+    })
+}
+
+#[test]
+fn sync_failed_hosts_continue() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // unreachable hosts
+        assert_cli_bin(test_id)
+            .with_args(&["host", "localhost:1", "add"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "localhost:2", "add"])
+            .succeeds()
+            .unwrap();
+
+        // every host is tried, failures are summarized
+        assert_cli_bin(test_id)
+            .with_args(&[
+                "sync",
+                "--non-interactive",
+                "--yes",
+                "--ssh-user",
+                "root",
+                "--authorized-keys-path",
+                "/root/.ssh/authorized_keys",
+            ])
+            .fails()
+            .stdout()
+            .contains("Sync failed for 3 host(s)")
+            .unwrap();
+
+        // database is saved, failed hosts are still pending
+        let mut database = String::new();
+        fs::File::open(settings_fixtures_copy(test_id))
+            .unwrap()
+            .read_to_string(&mut database)
+            .unwrap();
+        assert!(database.contains("\"schema_version\": \"0.2.0\""));
+        assert_eq!(database.matches("\"sync_todo\": true").count(), 3);
+    })
+}
+
+#[test]
+fn sync_jobs_failed_hosts() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // unreachable hosts
+        for host in &["localhost:1", "localhost:2", "localhost:3"] {
+            assert_cli_bin(test_id)
+                .with_args(&["host", host, "add"])
+                .succeeds()
+                .unwrap();
+        }
+
+        // hosts are synced by 2 workers, every host is in the summary
+        assert_cli_bin(test_id)
+            .with_args(&[
+                "sync",
+                "--non-interactive",
+                "--yes",
+                "--jobs",
+                "2",
+                "--ssh-user",
+                "root",
+            ])
+            .fails()
+            .stdout()
+            .contains("# Summary")
+            .stdout()
+            .contains("localhost:1")
+            .stdout()
+            .contains("localhost:2")
+            .stdout()
+            .contains("localhost:3")
+            .stdout()
+            .contains("0 synced, 4 failed, 0 skipped")
+            .stdout()
+            .contains("Sync failed for 4 host(s)")
+            .unwrap();
+
+        // database is saved, failed hosts are still pending
+        let mut database = String::new();
+        fs::File::open(settings_fixtures_copy(test_id))
+            .unwrap()
+            .read_to_string(&mut database)
+            .unwrap();
+        assert_eq!(database.matches("\"sync_todo\": true").count(), 4);
+    })
+}
+
+#[test]
+fn host_restore_deny() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // unknown host
+        assert_cli_bin(test_id)
+            .with_args(&["host", "unknown.example.com", "restore"])
+            .fails()
+            .stdout()
+            .contains("Hostname unknown.example.com not known")
+            .unwrap();
+
+        // no backups yet
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "restore"])
+            .fails()
+            .stdout()
+            .contains("No backups of host existing.example.com found")
+            .unwrap();
+
+        // non-interactive without --yes
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "restore", "--non-interactive"])
+            .fails()
+            .stdout()
+            .contains("requires --yes")
+            .unwrap();
+    })
+}
+
+#[test]
+fn host_trust() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // unknown host
+        assert_cli_bin(test_id)
+            .with_args(&["host", "unknown.example.com", "trust"])
+            .fails()
+            .stdout()
+            .contains("Hostname unknown.example.com not known")
+            .unwrap();
+
+        // non-interactive without --yes
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "trust", "--non-interactive"])
+            .fails()
+            .stdout()
+            .contains("requires --yes")
+            .unwrap();
+
+        // unreachable host, nothing is pinned
+        assert_cli_bin(test_id)
+            .with_args(&["host", "localhost:1", "add"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "localhost:1", "trust", "--non-interactive", "--yes"])
+            .fails()
+            .stdout()
+            .contains("Connection refused")
+            .unwrap();
+
+        // pin a host key of the first host in the database
+        let mut database = String::new();
+        fs::File::open(settings_fixtures_copy(test_id))
+            .unwrap()
+            .read_to_string(&mut database)
+            .unwrap();
+        fs::File::create(settings_fixtures_copy(test_id))
+            .unwrap()
+            .write_all(
+                database
+                    .replacen(
+                        "\"host_keys\": []",
+                        "\"host_keys\": [{\"algorithm\": \"ssh-ed25519\", \"fingerprint\": \"SHA256:a5lcfVLkzdMXMaOPcon5tzlRKbyidKwb6TIZpBUcidM\", \"added_at\": \"2018-08-18 12:00:00 UTC\"}]",
+                        1,
+                    )
+                    .as_bytes(),
+            )
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .contains("* ssh-ed25519 SHA256:a5lcfVLkzdMXMaOPcon5tzlRKbyidKwb6TIZpBUcidM")
+            .unwrap();
+    })
+}
+
+#[test]
+fn host_proxy_jump() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // unknown host
+        assert_cli_bin(test_id)
+            .with_args(&["host", "unknown.example.com", "proxy-jump", "bastion"])
+            .fails()
+            .stdout()
+            .contains("Hostname unknown.example.com not known")
+            .unwrap();
+
+        // nothing to remove
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "proxy-jump"])
+            .fails()
+            .stdout()
+            .contains("No jump host set for host existing")
+            .unwrap();
+
+        // empty hop
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "proxy-jump", "jump1,,jump2"])
+            .fails()
+            .unwrap();
+
+        // set a chain
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "proxy-jump", "jump1,deploy@jump2:2222"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .contains("ProxyJump: jump1,deploy@jump2:2222")
+            .unwrap();
+
+        // remove
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "proxy-jump"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .doesnt_contain("ProxyJump")
+            .unwrap();
+    })
+}
+
+#[test]
+fn host_accounts() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // group dev-ops add
+        assert_cli_bin(test_id)
+            .with_args(&["group", "dev-ops", "add"])
+            .succeeds()
+            .unwrap();
+
+        // grant to an undeclared account
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "grant", "existing", "--as", "deploy"])
+            .fails()
+            .stdout()
+            .contains("Host existing has no account deploy")
+            .unwrap();
+
+        // invalid account name
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "account", "add", "de ploy"])
+            .fails()
+            .unwrap();
+
+        // host existing account add deploy
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "account", "add", "deploy"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "account", "add", "deploy"])
+            .fails()
+            .unwrap();
+
+        // grants of the same user to two accounts of a host
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "grant", "existing", "--as", "deploy"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "grant", "existing", "--as", "deploy"])
+            .fails()
+            .stdout()
+            .contains("existing-user already granted to access deploy@existing")
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["group", "dev-ops", "grant", "existing", "--as", "deploy"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .contains("## Accounts\n* deploy")
+            .stdout()
+            .contains("* existing-user\n* existing-user as deploy")
+            .stdout()
+            .contains("* dev-ops as deploy")
+            .unwrap();
+
+        // accounts with grants can't be removed
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "account", "remove", "deploy"])
+            .fails()
+            .unwrap();
+
+        // revoke of the account keeps the grant without account
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "revoke", "existing", "--as", "deploy"])
+            .succeeds()
+            .unwrap();
+        assert_cli_bin(test_id)
+            .with_args(&["group", "dev-ops", "revoke", "existing", "--as", "deploy"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "list"])
+            .succeeds()
+            .stdout()
+            .contains("* existing-user\n")
+            .stdout()
+            .doesnt_contain("as deploy")
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "account", "remove", "deploy"])
+            .succeeds()
+            .unwrap();
+    })
+}
+
+#[test]
+fn host_groups() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // host 1.example.com add
+        assert_cli_bin(test_id)
+            .with_args(&["host", "1.example.com", "add"])
+            .succeeds()
+            .unwrap();
+
+        // hostgroup web add
+        assert_cli_bin(test_id)
+            .with_args(&["hostgroup", "web", "add"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["hostgroup", "web", "add"])
+            .fails()
+            .unwrap();
+
+        // hostnames, aliases and host groups share a namespace
+        assert_cli_bin(test_id)
+            .with_args(&["hostgroup", "existing", "add"])
+            .fails()
+            .unwrap();
+        assert_cli_bin(test_id)
+            .with_args(&["host", "web", "add"])
+            .fails()
+            .unwrap();
+
+        // hostgroup web add-host
+        assert_cli_bin(test_id)
+            .with_args(&["hostgroup", "web", "add-host", "existing"])
+            .succeeds()
+            .unwrap();
+        assert_cli_bin(test_id)
+            .with_args(&["hostgroup", "web", "add-host", "1.example.com"])
+            .succeeds()
+            .unwrap();
+        assert_cli_bin(test_id)
+            .with_args(&["hostgroup", "web", "add-host", "unknown.example.com"])
+            .fails()
+            .unwrap();
+
+        // user existing-user grant web
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "grant", "web", "--option", "no-pty"])
+            .succeeds()
+            .stdout()
+            .contains("Successfully granted user existing-user to host group web")
+            .unwrap();
+
+        // accounts of grants need to exist on all hosts
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "grant", "web", "--as", "deploy"])
+            .fails()
+            .unwrap();
+
+        // effective grants
+        assert_cli_bin(test_id)
+            .with_args(&["host", "1.example.com", "list"])
+            .succeeds()
+            .stdout()
+            .contains("## Host Groups\n* web")
+            .stdout()
+            .contains("* existing-user [no-pty] (via host group web)")
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["hostgroup", "list"])
+            .succeeds()
+            .stdout()
+            .contains("## Hosts\n* existing.example.com\n* 1.example.com")
+            .unwrap();
+
+        // hostgroup web remove-host
+        assert_cli_bin(test_id)
+            .with_args(&["hostgroup", "web", "remove-host", "1.example.com"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "1.example.com", "list"])
+            .succeeds()
+            .stdout()
+            .doesnt_contain("via host group web")
+            .unwrap();
+
+        // user existing-user revoke web
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "revoke", "web"])
+            .succeeds()
+            .unwrap();
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "revoke", "web"])
+            .fails()
+            .unwrap();
+
+        // hostgroup web remove
+        assert_cli_bin(test_id)
+            .with_args(&["hostgroup", "web", "remove"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["hostgroup", "list", "--raw"])
+            .succeeds()
+            .stdout()
+            .doesnt_contain("web")
+            .unwrap();
+    })
+}
+
+#[test]
+fn host_groups_user_remove() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        for args in &[
+            vec!["host", "1.example.com", "add"],
+            vec!["host", "2.example.com", "add"],
+            vec!["hostgroup", "web", "add"],
+            vec!["hostgroup", "web", "add-host", "1.example.com"],
+            vec!["user", "existing-user", "grant", "web"],
+        ] {
+            assert_cli_bin(test_id).with_args(&args).succeeds().unwrap();
+        }
+
+        // all hosts synced
+        let mut database = String::new();
+        fs::File::open(settings_fixtures_copy(test_id))
+            .unwrap()
+            .read_to_string(&mut database)
+            .unwrap();
+        fs::File::create(settings_fixtures_copy(test_id))
+            .unwrap()
+            .write_all(
+                database
+                    .replace("\"sync_todo\": true", "\"sync_todo\": false")
+                    .as_bytes(),
+            )
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "remove"])
+            .succeeds()
+            .unwrap();
+
+        // existing directly, 1.example.com through host group web need a sync
+        let mut database = String::new();
+        fs::File::open(settings_fixtures_copy(test_id))
+            .unwrap()
+            .read_to_string(&mut database)
+            .unwrap();
+        assert_eq!(database.matches("\"sync_todo\": true").count(), 2);
+        // 2.example.com stays synced
+        assert_eq!(database.matches("\"sync_todo\": false").count(), 1);
+    })
+}
+
+#[test]
+fn group_nested() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // groups eng, team-a and team-b
+        for group_id in &["eng", "team-a", "team-b"] {
+            assert_cli_bin(test_id)
+                .with_args(&["group", group_id, "add"])
+                .succeeds()
+                .unwrap();
+        }
+
+        assert_cli_bin(test_id)
+            .with_args(&["group", "team-b", "add", "existing-user"])
+            .succeeds()
+            .unwrap();
+
+        // eng > team-a > team-b
+        assert_cli_bin(test_id)
+            .with_args(&["group", "eng", "add-group", "team-a"])
+            .succeeds()
+            .unwrap();
+        assert_cli_bin(test_id)
+            .with_args(&["group", "team-a", "add-group", "team-b"])
+            .succeeds()
+            .unwrap();
+
+        // cycles
+        assert_cli_bin(test_id)
+            .with_args(&["group", "team-b", "add-group", "eng"])
+            .fails()
+            .stdout()
+            .contains("would create a cycle")
+            .unwrap();
+        assert_cli_bin(test_id)
+            .with_args(&["group", "eng", "add-group", "eng"])
+            .fails()
+            .unwrap();
+
+        // unknown group
+        assert_cli_bin(test_id)
+            .with_args(&["group", "eng", "add-group", "unknown"])
+            .fails()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["group", "eng", "list"])
+            .succeeds()
+            .stdout()
+            .contains("## Member Groups\n* team-a")
+            .stdout()
+            .contains("## Inherited Members\n* existing-user (via team-b)")
+            .unwrap();
+
+        // inherited members are removed from the nested group
+        assert_cli_bin(test_id)
+            .with_args(&["group", "eng", "remove", "existing-user"])
+            .fails()
+            .stdout()
+            .contains("through group team-b")
+            .unwrap();
+
+        // direct and inherited
+        assert_cli_bin(test_id)
+            .with_args(&["group", "eng", "add", "existing-user"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["group", "eng", "list"])
+            .succeeds()
+            .stdout()
+            .contains("## Members\n* existing-user")
+            .stdout()
+            .doesnt_contain("Inherited Members")
+            .unwrap();
+
+        // remove-group
+        assert_cli_bin(test_id)
+            .with_args(&["group", "eng", "remove-group", "team-a"])
+            .succeeds()
+            .unwrap();
+        assert_cli_bin(test_id)
+            .with_args(&["group", "eng", "remove-group", "team-a"])
+            .fails()
+            .unwrap();
+
+        // removed groups are removed from the groups containing them
+        assert_cli_bin(test_id)
+            .with_args(&["group", "team-b", "remove"])
+            .succeeds()
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["group", "team-a", "list"])
+            .succeeds()
+            .stdout()
+            .doesnt_contain("team-b")
+            .unwrap();
+    })
+}
+
+#[test]
+fn access_report() {
+    let test_id = line!();
+
+    run_test(test_id, || {
+        // unknown user and host
+        assert_cli_bin(test_id)
+            .with_args(&["user", "unknown", "access"])
+            .fails()
+            .unwrap();
+        assert_cli_bin(test_id)
+            .with_args(&["host", "unknown.example.com", "access"])
+            .fails()
+            .unwrap();
+
+        // existing-user through group eng > team on host group web
+        for args in &[
+            vec!["host", "web1.example.com", "add"],
+            vec!["hostgroup", "web", "add"],
+            vec!["hostgroup", "web", "add-host", "web1.example.com"],
+            vec!["group", "eng", "add"],
+            vec!["group", "team", "add"],
+            vec!["group", "team", "add", "existing-user"],
+            vec!["group", "eng", "add-group", "team"],
+            vec!["group", "eng", "grant", "web"],
+        ] {
+            assert_cli_bin(test_id).with_args(args).succeeds().unwrap();
+        }
+
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "access"])
+            .succeeds()
+            .stdout()
+            .contains("* existing.example.com (direct)")
+            .stdout()
+            .contains("* web1.example.com (via group eng, nested group team, host group web)")
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "web1.example.com", "access"])
+            .succeeds()
+            .stdout()
+            .contains("* existing-user (via group eng, nested group team, host group web)")
+            .unwrap();
+
+        assert_cli_bin(test_id)
+            .with_args(&["access", "--csv"])
+            .succeeds()
+            .stdout()
+            .contains("user,existing.example.com,web1.example.com")
+            .stdout()
+            .contains("existing-user,direct,\"via group eng, nested group team, host group web\"")
+            .unwrap();
+
+        // expired grants are no access
+        assert_cli_bin(test_id)
+            .with_args(&["user", "existing-user", "grant", "existing", "--until", "2099-01-01"])
+            .succeeds()
+            .unwrap();
+
+        grant_expire(test_id, "2099-01-01");
+
+        assert_cli_bin(test_id)
+            .with_args(&["host", "existing", "access"])
+            .succeeds()
+            .stdout()
+            .doesnt_contain("existing-user")
+            .unwrap();
+    })
+}